@@ -1,23 +1,26 @@
-use crate::err::ProfError;
-use crate::parse::cachegrind::Metric;
-use crate::profiler::Profiler;
+use cargo_profiler::backend::{names, registry};
+use cargo_profiler::cache::preset_args;
+use cargo_profiler::cargo::{BuildOptions, Target};
+use cargo_profiler::err::ProfError;
+use cargo_profiler::report::OutputFormat;
+use cargo_profiler::{Backend, Builder};
 use clap::ArgMatches;
 use std::path::Path;
 
-/// match the profiler argument
+/// match the profiler argument against the backends and configure the matching one
 pub fn get_profiler<'a>(
     matches: &'a ArgMatches,
-) -> Result<(&'a ArgMatches<'a>, Profiler), ProfError> {
-    match matches.subcommand_matches("profiler") {
-        Some(matches) => match matches.subcommand_matches("callgrind") {
-            Some(matches) => Ok((matches, Profiler::new_callgrind())),
-            None => match matches.subcommand_matches("cachegrind") {
-                Some(matches) => Ok((matches, Profiler::new_cachegrind())),
-                None => Err(ProfError::InvalidProfiler),
-            },
-        },
-        None => Err(ProfError::InvalidProfiler),
+) -> Result<(&'a ArgMatches<'a>, Box<dyn Backend>), ProfError> {
+    let matches = matches
+        .subcommand_matches("profiler")
+        .ok_or_else(|| ProfError::InvalidProfiler(names()))?;
+    for mut backend in registry() {
+        if let Some(matches) = matches.subcommand_matches(backend.name()) {
+            backend.configure(matches)?;
+            return Ok((matches, backend));
+        }
     }
+    Err(ProfError::InvalidProfiler(names()))
 }
 
 /// match the binary argument
@@ -34,6 +37,56 @@ pub fn get_binary<'a>(matches: &'a ArgMatches) -> Result<&'a str, ProfError> {
     }
 }
 
+/// collect the options for building the binary with cargo
+pub fn get_build_options(matches: &ArgMatches) -> Result<BuildOptions, ProfError> {
+    let targets = [
+        matches.value_of("bin").map(|x| Target::Bin(x.to_string())),
+        matches
+            .value_of("example")
+            .map(|x| Target::Example(x.to_string())),
+        matches
+            .value_of("test")
+            .map(|x| Target::Test(x.to_string())),
+        matches
+            .value_of("bench")
+            .map(|x| Target::Bench(x.to_string())),
+    ];
+    let mut targets = targets.iter().flatten().cloned();
+    let target = targets.next().unwrap_or_default();
+    if targets.next().is_some() {
+        return Err(ProfError::InvalidBinary);
+    }
+
+    Ok(BuildOptions {
+        package: matches.value_of("package").map(|x| x.to_string()),
+        target,
+        release: matches.is_present("release"),
+        profile: matches.value_of("profile").map(|x| x.to_string()),
+        features: matches
+            .values_of("features")
+            .map(|x| x.map(|x| x.to_string()).collect())
+            .unwrap_or_default(),
+        no_default_features: matches.is_present("no-default-features"),
+        target_triple: matches.value_of("target").map(|x| x.to_string()),
+        target_dir: matches.value_of("target-dir").map(|x| x.to_string()),
+        no_debug_info: matches.is_present("no-debug-info"),
+        verbose: true,
+    })
+}
+
+/// collect the extra arguments for valgrind, cache presets of callgrind and cachegrind
+/// first so they can be overridden
+pub fn get_valgrind_args(matches: &ArgMatches) -> Result<Vec<String>, ProfError> {
+    let mut args = match matches.value_of("cache-config") {
+        Some(name) => preset_args(name)?,
+        None => vec![],
+    };
+    if let Some(values) = matches.values_of("valgrind-arg") {
+        args.extend(values.map(|x| x.to_string()));
+    }
+    Ok(args)
+}
+
 /// parse the number argument into a usize
 pub fn get_num(matches: &ArgMatches) -> Result<usize, ProfError> {
     match matches.value_of("n").map(|x| x.parse::<usize>()) {
@@ -43,20 +96,28 @@ pub fn get_num(matches: &ArgMatches) -> Result<usize, ProfError> {
     }
 }
 
-/// get the cachegrind metric user wants to sort on
-pub fn get_sort_metric(matches: &ArgMatches) -> Result<Metric, ProfError> {
-    match matches.value_of("sort") {
-        Some("ir") => Ok(Metric::Ir),
-        Some("i1mr") => Ok(Metric::I1mr),
-        Some("ilmr") => Ok(Metric::ILmr),
-        Some("dr") => Ok(Metric::Dr),
-        Some("d1mr") => Ok(Metric::D1mr),
-        Some("dlmr") => Ok(Metric::DLmr),
-        Some("dw") => Ok(Metric::Dw),
-        Some("d1mw") => Ok(Metric::D1mw),
-        Some("dlmw") => Ok(Metric::DLmw),
-        None => Ok(Metric::NAN),
-        _ => Err(ProfError::InvalidSortMetric),
+/// set up the profile the user asked for
+pub fn get_builder(matches: &ArgMatches, backend: Box<dyn Backend>) -> Result<Builder, ProfError> {
+    let mut builder = Builder::with_backend(backend)
+        .build(get_build_options(matches)?)
+        .args(matches.values_of_os("binargs").into_iter().flatten())
+        .valgrind_args(get_valgrind_args(matches)?)
+        .num(get_num(matches)?)
+        .keep_output(matches.is_present("keep"));
+    if matches.is_present("binary") {
+        builder = builder.binary(get_binary(matches)?);
+    }
+    Ok(builder)
+}
+
+/// get the format the user wants the results in
+pub fn get_output_format(matches: &ArgMatches) -> Result<OutputFormat, ProfError> {
+    match matches.value_of("output-format") {
+        Some("text") | None => Ok(OutputFormat::Text),
+        Some("json") => Ok(OutputFormat::Json),
+        Some("csv") => Ok(OutputFormat::Csv),
+        Some("tsv") => Ok(OutputFormat::Tsv),
+        _ => Err(ProfError::InvalidOutputFormat),
     }
 }
 
@@ -77,9 +138,4 @@ mod test {
     fn test_get_num() {
         assert_eq!(1, 1);
     }
-
-    #[test]
-    fn test_get_sort_metric() {
-        assert_eq!(1, 1);
-    }
 }
@@ -0,0 +1,227 @@
+use crate::backend::{cache_config_arg, group_by_arg, Backend, Results};
+use crate::err::ProfError;
+use crate::parse::cachegrind::{parse, sort_column, Latencies, CYCLES};
+use crate::profiler::CacheGrindResults;
+use crate::report::{function, GroupBy, Metadata, Report};
+use clap::{Arg, ArgMatches};
+use std::any::Any;
+
+/// Cache and branch simulation from `valgrind --tool=cachegrind`.
+#[derive(Clone, Debug, Default)]
+pub struct CacheGrind {
+    /// event to sort by, `Ir` if not given
+    pub sort: Option<String>,
+    /// latencies for the estimated cycles
+    pub latencies: Latencies,
+    /// simulate the branch predictor
+    pub branch_sim: bool,
+    /// add up the costs of the functions of a crate, module or file
+    pub group_by: GroupBy,
+}
+
+/// Events of every cachegrind profile, as caches are always simulated.
+const CACHE_EVENTS: [&str; 9] = [
+    "Ir", "I1mr", "ILmr", "Dr", "D1mr", "DLmr", "Dw", "D1mw", "DLmw",
+];
+
+/// Events added by `--branch-sim`.
+const BRANCH_EVENTS: [&str; 4] = ["Bc", "Bcm", "Bi", "Bim"];
+
+impl CacheGrind {
+    /// Events the profile will have, including the estimated cycles.
+    fn events(&self) -> Vec<String> {
+        let branches = if self.branch_sim {
+            &BRANCH_EVENTS[..]
+        } else {
+            &[]
+        };
+        CACHE_EVENTS
+            .iter()
+            .chain(branches.iter())
+            .chain([CYCLES].iter())
+            .map(|x| x.to_string())
+            .collect()
+    }
+}
+
+/// Parse the `L1,LL,RAM` latencies for estimating cycles.
+fn parse_latencies(value: &str) -> Result<Latencies, ProfError> {
+    let invalid = || ProfError::InvalidLatencies(value.to_string());
+
+    let latencies = value
+        .split(',')
+        .map(|x| x.trim().parse::<f64>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+    match latencies[..] {
+        [l1, ll, ram] if latencies.iter().all(|x| *x >= 0.) => Ok(Latencies { l1, ll, ram }),
+        _ => Err(invalid()),
+    }
+}
+
+impl Backend for CacheGrind {
+    fn name(&self) -> &'static str {
+        "cachegrind"
+    }
+
+    fn about(&self) -> &'static str {
+        "gets cachegrind features"
+    }
+
+    fn metrics(&self) -> &'static [(&'static str, &'static str)] {
+        &[
+            ("Ir", "Total Instructions"),
+            ("I1mr", "Level 1 I-Cache misses"),
+            ("ILmr", "Last Level I-Cache misses"),
+            ("Dr", "Total Memory Reads"),
+            ("D1mr", "Level 1 D-Cache read misses"),
+            ("DLmr", "Last Level D-cache read misses"),
+            ("Dw", "Total Memory Writes"),
+            ("D1mw", "Level 1 D-Cache write misses"),
+            ("DLmw", "Last Level D-cache write misses"),
+            ("Bc", "Conditional branches executed, with --branch-sim"),
+            (
+                "Bcm",
+                "Conditional branches mispredicted, with --branch-sim",
+            ),
+            ("Bi", "Indirect branches executed, with --branch-sim"),
+            ("Bim", "Indirect branches mispredicted, with --branch-sim"),
+            ("Cycles", "Estimated cycles, see --latencies"),
+        ]
+    }
+
+    fn args(&self) -> Vec<Arg<'static, 'static>> {
+        vec![
+            // create sort metric argument
+            Arg::with_name("sort")
+                .long("sort")
+                .value_name("SORT")
+                .takes_value(true)
+                .help("metric you want to sort by"),
+            // simulate the branch predictor
+            Arg::with_name("branch-sim")
+                .long("branch-sim")
+                .required(false)
+                .help("count conditional and indirect branches and their mispredictions"),
+            // latencies for the estimated cycles
+            Arg::with_name("latencies")
+                .long("latencies")
+                .value_name("L1,LL,RAM")
+                .takes_value(true)
+                .help("cycles of an L1 hit, LL hit and RAM access for estimating cycles (1,5,35)"),
+            cache_config_arg(),
+            group_by_arg(),
+        ]
+    }
+
+    fn configure(&mut self, matches: &ArgMatches) -> Result<(), ProfError> {
+        self.sort = matches.value_of("sort").map(|x| x.to_string());
+        if let Some(value) = matches.value_of("latencies") {
+            self.latencies = parse_latencies(value)?;
+        }
+        self.branch_sim = matches.is_present("branch-sim");
+        // reject an unknown event before running valgrind, not after
+        sort_column(&self.events(), self.sort.as_deref())?;
+        if let Some(value) = matches.value_of("group-by") {
+            self.group_by = value.parse()?;
+        }
+        Ok(())
+    }
+
+    // newer valgrind versions only count instructions unless asked to simulate caches
+    fn tool_args(&self) -> Vec<String> {
+        let mut args = vec!["--cache-sim=yes".to_string()];
+        if self.branch_sim {
+            args.push("--branch-sim=yes".to_string());
+        }
+        args
+    }
+
+    fn parse(&self, output: &str, num: usize) -> Result<Box<dyn Results>, ProfError> {
+        Ok(Box::new(parse(
+            output,
+            num,
+            self.sort.as_deref(),
+            &self.latencies,
+            self.group_by,
+        )?))
+    }
+}
+
+impl Results for CacheGrindResults {
+    fn report(&self, metadata: Metadata) -> Report {
+        let functions = self
+            .data
+            .outer_iter()
+            .zip(self.functs.iter())
+            .map(|(x, y)| function(y, self.events.iter().cloned().zip(x.to_vec()).collect()))
+            .collect();
+        let totals = self
+            .events
+            .iter()
+            .cloned()
+            .zip(self.totals.iter().cloned())
+            .collect();
+
+        Report::new(
+            "cachegrind",
+            metadata,
+            self.events.clone(),
+            totals,
+            functions,
+        )
+    }
+
+    fn metric(&self) -> &str {
+        &self.metric
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_latencies, CacheGrind};
+    use crate::backend::Backend;
+    use clap::App;
+
+    #[test]
+    fn test_configure_sort() {
+        let configure = |args: &[&str]| {
+            let mut backend = CacheGrind::default();
+            let matches = App::new("cachegrind")
+                .args(&backend.args())
+                .get_matches_from(std::iter::once("cachegrind").chain(args.iter().cloned()));
+            backend.configure(&matches).map(|_| backend)
+        };
+        assert_eq!(
+            configure(&["--sort", "d1mr"]).unwrap().sort.unwrap(),
+            "d1mr"
+        );
+        assert!(configure(&["--sort", "cycles"]).is_ok());
+        assert!(configure(&["--sort", "bc"]).is_err());
+        assert!(configure(&["--sort", "bc", "--branch-sim"]).is_ok());
+        assert!(configure(&["--sort", "foo"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_latencies() {
+        let latencies = parse_latencies("1, 12,200").unwrap();
+        assert_eq!((latencies.l1, latencies.ll, latencies.ram), (1., 12., 200.));
+        assert!(parse_latencies("1,2").is_err());
+        assert!(parse_latencies("1,-2,3").is_err());
+    }
+
+    #[test]
+    fn test_tool_args() {
+        let backend = CacheGrind {
+            branch_sim: true,
+            ..CacheGrind::default()
+        };
+        assert_eq!(
+            backend.tool_args(),
+            vec!["--cache-sim=yes", "--branch-sim=yes"]
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use crate::backend::{cache_config_arg, group_by_arg, Backend, Results};
+use crate::callgraph::CallGraph;
+use crate::err::ProfError;
+use crate::parse::callgrind::parse;
+use crate::profiler::CallGrindResults;
+use crate::report::{function, GroupBy, Metadata, Report};
+use clap::{Arg, ArgMatches};
+use std::any::Any;
+
+/// Instruction counts and call graph from `valgrind --tool=callgrind`.
+#[derive(Clone, Debug, Default)]
+pub struct CallGrind {
+    /// sort functions by inclusive instead of self cost
+    pub inclusive: bool,
+    /// add up the costs of the functions of a crate, module or file
+    pub group_by: GroupBy,
+}
+
+impl Backend for CallGrind {
+    fn name(&self) -> &'static str {
+        "callgrind"
+    }
+
+    fn about(&self) -> &'static str {
+        "gets callgrind features"
+    }
+
+    fn metrics(&self) -> &'static [(&'static str, &'static str)] {
+        &[
+            ("Ir", "instructions executed in the function itself"),
+            (
+                "Ir_inclusive",
+                "instructions executed in the function and everything it calls",
+            ),
+        ]
+    }
+
+    fn args(&self) -> Vec<Arg<'static, 'static>> {
+        vec![
+            // sort callgrind functions by inclusive cost
+            Arg::with_name("inclusive")
+                .long("inclusive")
+                .required(false)
+                .help("sort functions by inclusive instead of self cost"),
+            // show callers and callees of a function
+            Arg::with_name("focus")
+                .long("focus")
+                .value_name("FUNCTION")
+                .takes_value(true)
+                .help("show who calls the matching functions and what they call"),
+            // write a flamegraph
+            Arg::with_name("flamegraph")
+                .long("flamegraph")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("write an interactive SVG flamegraph to FILE"),
+            cache_config_arg(),
+            group_by_arg(),
+        ]
+    }
+
+    fn configure(&mut self, matches: &ArgMatches) -> Result<(), ProfError> {
+        self.inclusive = matches.is_present("inclusive");
+        if let Some(value) = matches.value_of("group-by") {
+            self.group_by = value.parse()?;
+        }
+        Ok(())
+    }
+
+    fn parse(&self, output: &str, num: usize) -> Result<Box<dyn Results>, ProfError> {
+        Ok(Box::new(parse(output, num, self.inclusive, self.group_by)?))
+    }
+}
+
+impl Results for CallGrindResults {
+    fn report(&self, metadata: Metadata) -> Report {
+        let events = vec!["Ir".to_string(), "Ir_inclusive".to_string()];
+        let functions = self
+            .instructions
+            .iter()
+            .zip(self.inclusive.iter())
+            .zip(self.functs.iter())
+            .map(|((&x, &incl), y)| {
+                let metrics = events.iter().cloned().zip(vec![x, incl]).collect();
+                function(y, metrics)
+            })
+            .collect();
+        let totals = vec![("Ir".to_string(), self.total_instructions)]
+            .into_iter()
+            .collect();
+
+        Report::new("callgrind", metadata, events, totals, functions)
+    }
+
+    fn metric(&self) -> &str {
+        &self.metric
+    }
+
+    fn graph(&self) -> Option<&CallGraph> {
+        Some(&self.graph)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -0,0 +1,97 @@
+use crate::backend::{Backend, Results};
+use crate::err::ProfError;
+use crate::parse::dhat::{parse, METRICS};
+use crate::profiler::DhatResults;
+use crate::report::{function, Metadata, Report};
+use clap::{Arg, ArgMatches};
+use std::any::Any;
+
+/// Allocation sites, their lifetimes and accesses from `valgrind --tool=dhat`.
+#[derive(Clone, Debug, Default)]
+pub struct Dhat {
+    /// metric to sort by, `total_B` if not given
+    pub sort: Option<String>,
+}
+
+impl Backend for Dhat {
+    fn name(&self) -> &'static str {
+        "dhat"
+    }
+
+    fn about(&self) -> &'static str {
+        "gets dhat allocation profile"
+    }
+
+    fn metrics(&self) -> &'static [(&'static str, &'static str)] {
+        &[
+            ("total_B", "bytes allocated"),
+            ("total_blocks", "blocks allocated"),
+            (
+                "avg_lifetime",
+                "average lifetime of a block, in instructions",
+            ),
+            ("reads_per_B", "bytes read per allocated byte"),
+            ("writes_per_B", "bytes written per allocated byte"),
+        ]
+    }
+
+    fn args(&self) -> Vec<Arg<'static, 'static>> {
+        vec![
+            // create sort metric argument
+            Arg::with_name("sort")
+                .long("sort")
+                .value_name("SORT")
+                .takes_value(true)
+                .help("metric you want to sort by"),
+        ]
+    }
+
+    fn configure(&mut self, matches: &ArgMatches) -> Result<(), ProfError> {
+        self.sort = matches.value_of("sort").map(|x| x.to_string());
+        Ok(())
+    }
+
+    fn parse(&self, output: &str, num: usize) -> Result<Box<dyn Results>, ProfError> {
+        Ok(Box::new(parse(output, num, self.sort.as_deref())?))
+    }
+}
+
+impl Results for DhatResults {
+    fn report(&self, metadata: Metadata) -> Report {
+        let events = METRICS
+            .iter()
+            .enumerate()
+            .filter(|&(idx, _)| self.sites.iter().all(|x| x.metrics()[idx].is_some()))
+            .map(|(_, x)| x.to_string())
+            .collect::<Vec<_>>();
+        let functions = self
+            .sites
+            .iter()
+            .map(|x| {
+                let metrics = METRICS
+                    .iter()
+                    .zip(x.metrics())
+                    .filter_map(|(name, value)| value.map(|value| (name.to_string(), value)))
+                    .collect();
+                function(&x.funct, metrics)
+            })
+            .collect();
+        let totals = vec![
+            ("total_B".to_string(), self.total_bytes),
+            ("total_blocks".to_string(), self.total_blocks),
+            ("peak_B".to_string(), self.peak_bytes),
+        ]
+        .into_iter()
+        .collect();
+
+        Report::new("dhat", metadata, events, totals, functions)
+    }
+
+    fn metric(&self) -> &str {
+        &self.metric
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
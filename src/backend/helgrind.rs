@@ -0,0 +1,104 @@
+use crate::backend::{Backend, Results};
+use crate::err::ProfError;
+use crate::parse::races::parse;
+use crate::profiler::RaceResults;
+use crate::report::{function, Metadata, Report};
+use std::any::Any;
+
+/// Data races, lock order violations and misuse of the pthreads API from
+/// `valgrind --tool=helgrind` or `valgrind --tool=drd`. Drd is faster than helgrind on
+/// programs with many threads and shows the segment of the other thread instead of its
+/// access.
+#[derive(Clone, Debug)]
+pub struct RaceDetector {
+    tool: &'static str,
+    about: &'static str,
+}
+
+impl RaceDetector {
+    pub fn helgrind() -> RaceDetector {
+        RaceDetector {
+            tool: "helgrind",
+            about: "checks for data races with helgrind",
+        }
+    }
+
+    pub fn drd() -> RaceDetector {
+        RaceDetector {
+            tool: "drd",
+            about: "checks for data races with drd",
+        }
+    }
+}
+
+impl Backend for RaceDetector {
+    fn name(&self) -> &'static str {
+        self.tool
+    }
+
+    fn about(&self) -> &'static str {
+        self.about
+    }
+
+    fn metrics(&self) -> &'static [(&'static str, &'static str)] {
+        &[(
+            "errors",
+            "data races and lock misuse, per function those that happened in it",
+        )]
+    }
+
+    fn output_file(&self) -> String {
+        format!("{}.xml", self.tool)
+    }
+
+    fn output_arg(&self) -> String {
+        format!("--xml-file={}", self.output_file())
+    }
+
+    fn tool_args(&self) -> Vec<String> {
+        vec!["--xml=yes".to_string()]
+    }
+
+    fn parse(&self, output: &str, num: usize) -> Result<Box<dyn Results>, ProfError> {
+        Ok(Box::new(parse(output, num, self.tool)?))
+    }
+}
+
+impl Results for RaceResults {
+    fn report(&self, metadata: Metadata) -> Report {
+        let events = vec!["errors".to_string()];
+
+        // errors of every function, in the order of the reports
+        let mut sites: Vec<(String, f64)> = Vec::new();
+        for error in self.errors.iter() {
+            let label = error.site().map_or("???:???".to_string(), |x| x.label());
+            match sites.iter_mut().find(|x| x.0 == label) {
+                Some(site) => site.1 += error.count as f64,
+                None => sites.push((label, error.count as f64)),
+            }
+        }
+        let functions = sites
+            .iter()
+            .map(|(label, errors)| {
+                function(label, events.iter().cloned().zip(vec![*errors]).collect())
+            })
+            .collect();
+        let totals = vec![("errors".to_string(), self.failures as f64)]
+            .into_iter()
+            .collect();
+
+        Report::new(&self.tool, metadata, events, totals, functions)
+    }
+
+    fn metric(&self) -> &str {
+        "errors"
+    }
+
+    fn failures(&self) -> usize {
+        self.failures
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -0,0 +1,68 @@
+use crate::backend::{Backend, Results};
+use crate::err::ProfError;
+use crate::parse::massif::parse;
+use crate::profiler::MassifResults;
+use crate::report::{function, Metadata, Report};
+use std::any::Any;
+
+/// Heap usage over time from `valgrind --tool=massif`.
+#[derive(Clone, Debug, Default)]
+pub struct Massif;
+
+impl Backend for Massif {
+    fn name(&self) -> &'static str {
+        "massif"
+    }
+
+    fn about(&self) -> &'static str {
+        "gets massif heap profile"
+    }
+
+    fn metrics(&self) -> &'static [(&'static str, &'static str)] {
+        &[
+            (
+                "heap_B",
+                "heap bytes in use at the peak, per function those allocated through it",
+            ),
+            ("heap_extra_B", "allocator overhead at the peak"),
+            (
+                "stacks_B",
+                "stack bytes at the peak, with --valgrind-arg=--stacks=yes",
+            ),
+        ]
+    }
+
+    fn parse(&self, output: &str, num: usize) -> Result<Box<dyn Results>, ProfError> {
+        Ok(Box::new(parse(output, num)?))
+    }
+}
+
+impl Results for MassifResults {
+    fn report(&self, metadata: Metadata) -> Report {
+        let events = vec!["heap_B".to_string()];
+        let functions = self
+            .bytes
+            .iter()
+            .zip(self.functs.iter())
+            .map(|(&x, y)| function(y, events.iter().cloned().zip(vec![x]).collect()))
+            .collect();
+        let peak = &self.snapshots[self.peak];
+        let totals = vec![
+            ("heap_B".to_string(), peak.heap),
+            ("heap_extra_B".to_string(), peak.extra),
+            ("stacks_B".to_string(), peak.stacks),
+        ]
+        .into_iter()
+        .collect();
+
+        Report::new("massif", metadata, events, totals, functions)
+    }
+
+    fn metric(&self) -> &str {
+        "heap_B"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -0,0 +1,132 @@
+use crate::backend::{Backend, Results};
+use crate::err::ProfError;
+use crate::parse::memcheck::{is_failure, parse};
+use crate::profiler::MemcheckResults;
+use crate::report::{function, Metadata, Report};
+use std::any::Any;
+
+/// Invalid memory accesses, uses of uninitialised values and leaks from
+/// `valgrind --tool=memcheck`.
+#[derive(Clone, Debug, Default)]
+pub struct Memcheck;
+
+impl Backend for Memcheck {
+    fn name(&self) -> &'static str {
+        "memcheck"
+    }
+
+    fn about(&self) -> &'static str {
+        "checks for memory errors and leaks with memcheck"
+    }
+
+    fn metrics(&self) -> &'static [(&'static str, &'static str)] {
+        &[
+            (
+                "errors",
+                "memory errors and definite or possible leaks, per function those blamed on it",
+            ),
+            ("leaked_B", "bytes definitely or possibly lost"),
+        ]
+    }
+
+    fn output_file(&self) -> String {
+        "memcheck.xml".to_string()
+    }
+
+    fn output_arg(&self) -> String {
+        format!("--xml-file={}", self.output_file())
+    }
+
+    fn tool_args(&self) -> Vec<String> {
+        vec!["--xml=yes".to_string(), "--leak-check=full".to_string()]
+    }
+
+    fn parse(&self, output: &str, num: usize) -> Result<Box<dyn Results>, ProfError> {
+        Ok(Box::new(parse(output, num)?))
+    }
+}
+
+/// Bytes lost for good, i.e. definitely or possibly.
+fn leaked_bytes(kind: &str, bytes: f64) -> f64 {
+    match kind {
+        "Leak_DefinitelyLost" | "Leak_PossiblyLost" => bytes,
+        _ => 0.,
+    }
+}
+
+impl Results for MemcheckResults {
+    fn report(&self, metadata: Metadata) -> Report {
+        let events = vec!["errors".to_string(), "leaked_B".to_string()];
+
+        // errors and leaked bytes of every function, in the order memcheck found them
+        let mut sites: Vec<(String, f64, f64)> = Vec::new();
+        for error in self.errors.iter().filter(|x| is_failure(x)) {
+            let label = error.site().map_or("???:???".to_string(), |x| x.label());
+            let idx = match sites.iter().position(|x| x.0 == label) {
+                Some(idx) => idx,
+                None => {
+                    sites.push((label, 0., 0.));
+                    sites.len() - 1
+                }
+            };
+            sites[idx].1 += error.count as f64;
+            sites[idx].2 += leaked_bytes(&error.kind, error.leaked_bytes);
+        }
+        let functions = sites
+            .iter()
+            .map(|(label, errors, leaked)| {
+                function(
+                    label,
+                    events.iter().cloned().zip(vec![*errors, *leaked]).collect(),
+                )
+            })
+            .collect();
+        let totals = vec![
+            ("errors".to_string(), self.failures as f64),
+            (
+                "leaked_B".to_string(),
+                self.leaks.iter().map(|x| leaked_bytes(&x.0, x.1)).sum(),
+            ),
+        ]
+        .into_iter()
+        .collect();
+
+        Report::new("memcheck", metadata, events, totals, functions)
+    }
+
+    fn metric(&self) -> &str {
+        "errors"
+    }
+
+    fn failures(&self) -> usize {
+        self.failures
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Memcheck;
+    use crate::backend::Backend;
+    use std::ffi::OsStr;
+
+    #[test]
+    fn test_memcheck_command() {
+        let command = Memcheck.command("./foo", &[OsStr::new("-x")], &[]);
+        let args = command.get_args().collect::<Vec<_>>();
+        assert_eq!(
+            args,
+            vec![
+                "--tool=memcheck",
+                "--xml=yes",
+                "--leak-check=full",
+                "--xml-file=memcheck.xml",
+                "./foo",
+                "-x"
+            ]
+        );
+    }
+}
@@ -0,0 +1,311 @@
+//! Profiler backends. A backend knows how to run one valgrind tool, parse what it wrote and
+//! which metrics it reports. The command line gets one subcommand per backend of the
+//! [`registry`].
+
+pub mod cachegrind;
+pub mod callgrind;
+pub mod dhat;
+pub mod helgrind;
+pub mod massif;
+pub mod memcheck;
+
+use crate::callgraph::CallGraph;
+use crate::err::ProfError;
+use crate::report::{Metadata, Report};
+use clap::{Arg, ArgMatches};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::any::Any;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::process::Command;
+
+pub use self::cachegrind::CacheGrind;
+pub use self::callgrind::CallGrind;
+pub use self::dhat::Dhat;
+pub use self::helgrind::RaceDetector;
+pub use self::massif::Massif;
+pub use self::memcheck::Memcheck;
+
+/// Parsed results of a backend.
+pub trait Results: fmt::Display {
+    /// Costs of the functions, used for JSON and CSV output, baselines and budgets.
+    fn report(&self, metadata: Metadata) -> Report;
+
+    /// Metric the functions are sorted by. Baselines and budgets compare this metric unless
+    /// told otherwise.
+    fn metric(&self) -> &str;
+
+    /// Errors the tool found in the program, which make cargo profiler exit with an error.
+    fn failures(&self) -> usize {
+        0
+    }
+
+    /// Call graph of the program, for `--focus` and `--flamegraph`.
+    fn graph(&self) -> Option<&CallGraph> {
+        None
+    }
+
+    /// Access to the concrete results.
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// A valgrind tool cargo profiler can run.
+pub trait Backend: fmt::Debug {
+    /// Name of the subcommand and of the valgrind tool, e.g. `callgrind`.
+    fn name(&self) -> &'static str;
+
+    /// One line description for `--help`.
+    fn about(&self) -> &'static str;
+
+    /// Names and descriptions of the metrics the backend reports.
+    fn metrics(&self) -> &'static [(&'static str, &'static str)];
+
+    /// Command line arguments only this backend understands.
+    fn args(&self) -> Vec<Arg<'static, 'static>> {
+        Vec::new()
+    }
+
+    /// Pick up the values of the backend's arguments.
+    fn configure(&mut self, _matches: &ArgMatches) -> Result<(), ProfError> {
+        Ok(())
+    }
+
+    /// File the tool writes its results to.
+    fn output_file(&self) -> String {
+        format!("{}.out", self.name())
+    }
+
+    /// Argument telling the tool where to write its results.
+    fn output_arg(&self) -> String {
+        format!("--{}-out-file={}", self.name(), self.output_file())
+    }
+
+    /// Arguments of the tool itself, passed before any extra valgrind arguments so those
+    /// can override them.
+    fn tool_args(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// The valgrind command line that profiles `binary`.
+    fn command(&self, binary: &str, binargs: &[&OsStr], valgrind_args: &[String]) -> Command {
+        let mut command = Command::new("valgrind");
+        command
+            .arg(format!("--tool={}", self.name()))
+            .args(self.tool_args())
+            .arg(self.output_arg())
+            .args(valgrind_args)
+            .arg(binary)
+            .args(binargs);
+        command
+    }
+
+    /// Run the tool and return the contents of the file it wrote.
+    fn run(
+        &self,
+        binary: &str,
+        binargs: &[&OsStr],
+        valgrind_args: &[String],
+    ) -> Result<String, ProfError> {
+        lazy_static! {
+            static ref ERROR_REGEX: Regex =
+                Regex::new(r"Valgrind's memory management: out of memory").unwrap();
+        }
+
+        // never parse the results of an earlier run
+        let _ = fs::remove_file(self.output_file());
+
+        let valgrind = self
+            .command(binary, binargs, valgrind_args)
+            .output()
+            .or(Err(ProfError::CliError))?;
+
+        let stderr = String::from_utf8_lossy(&valgrind.stderr);
+        if ERROR_REGEX.is_match(&stderr) {
+            return Err(ProfError::OutOfMemoryError);
+        }
+
+        // valgrind exits with the status of the program, whose profile is still complete
+        let status = match valgrind.status.code() {
+            Some(code) => format!("exit status {}", code),
+            None => "a signal".to_string(),
+        };
+        let output = match fs::read_to_string(self.output_file()) {
+            Ok(output) => output,
+            Err(_) => {
+                let lines = stderr.lines().collect::<Vec<_>>();
+                let tail = lines[lines.len().saturating_sub(10)..].join("\n");
+                return Err(ProfError::ValgrindFailed(
+                    format!("{} and no {} written", status, self.output_file()),
+                    tail,
+                ));
+            }
+        };
+        if !valgrind.status.success() {
+            eprintln!(
+                "\x1b[1;33mwarning: \x1b[0mthe profiled program exited with {}.",
+                status
+            );
+        }
+
+        Ok(output)
+    }
+
+    /// Parse what the tool wrote, keeping the first `num` functions.
+    fn parse(&self, output: &str, num: usize) -> Result<Box<dyn Results>, ProfError>;
+}
+
+/// The `--group-by` argument of backends that report costs per function.
+fn group_by_arg() -> Arg<'static, 'static> {
+    Arg::with_name("group-by")
+        .long("group-by")
+        .value_name("GROUP")
+        .takes_value(true)
+        .help("add up the costs per crate, module, file or function (default)")
+}
+
+/// The `--cache-config` argument of the backends that simulate caches.
+fn cache_config_arg() -> Arg<'static, 'static> {
+    Arg::with_name("cache-config")
+        .long("cache-config")
+        .value_name("PRESET")
+        .takes_value(true)
+        .help("simulate the caches of skylake, zen3, neoverse-n1 or cortex-a72")
+}
+
+/// All backends, in the order of the subcommands.
+pub fn registry() -> Vec<Box<dyn Backend>> {
+    vec![
+        Box::new(CallGrind::default()),
+        Box::new(CacheGrind::default()),
+        Box::new(Massif),
+        Box::new(Dhat::default()),
+        Box::new(Memcheck),
+        Box::new(RaceDetector::helgrind()),
+        Box::new(RaceDetector::drd()),
+    ]
+}
+
+/// Names of all backends, in the order of the subcommands.
+pub fn names() -> Vec<String> {
+    registry().iter().map(|x| x.name().to_string()).collect()
+}
+
+/// The backend with the given name.
+pub fn find(name: &str) -> Result<Box<dyn Backend>, ProfError> {
+    registry()
+        .into_iter()
+        .find(|x| x.name() == name)
+        .ok_or_else(|| ProfError::InvalidProfiler(names()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{find, registry, CallGrind, Results};
+    use crate::backend::Backend;
+    use crate::err::ProfError;
+    use std::ffi::OsStr;
+    use std::fs;
+    use std::process::Command;
+
+    /// A tool that runs a shell script instead of valgrind.
+    #[derive(Debug)]
+    struct Script(&'static str);
+
+    impl Backend for Script {
+        fn name(&self) -> &'static str {
+            "script"
+        }
+
+        fn about(&self) -> &'static str {
+            ""
+        }
+
+        fn metrics(&self) -> &'static [(&'static str, &'static str)] {
+            &[]
+        }
+
+        fn output_file(&self) -> String {
+            std::env::temp_dir()
+                .join("cargo-profiler-test-script.out")
+                .display()
+                .to_string()
+        }
+
+        fn command(&self, _: &str, _: &[&OsStr], _: &[String]) -> Command {
+            let mut command = Command::new("sh");
+            command.arg("-c").arg(self.0).env("OUT", self.output_file());
+            command
+        }
+
+        fn parse(&self, _: &str, _: usize) -> Result<Box<dyn Results>, ProfError> {
+            Err(ProfError::CliError)
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let names = registry().iter().map(|x| x.name()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "callgrind",
+                "cachegrind",
+                "massif",
+                "dhat",
+                "memcheck",
+                "helgrind",
+                "drd"
+            ]
+        );
+        assert_eq!(find("cachegrind").unwrap().output_file(), "cachegrind.out");
+        assert_eq!(find("drd").unwrap().output_arg(), "--xml-file=drd.xml");
+        let err = find("massif2").unwrap_err().to_string();
+        assert!(err.contains("callgrind, cachegrind, massif, dhat, memcheck, helgrind, drd."));
+    }
+
+    #[test]
+    fn test_run() {
+        let run = |script| Script(script).run("./foo", &[], &[]);
+        assert_eq!(run("echo ok > $OUT").unwrap(), "ok\n");
+
+        // a file left over from the run above is not picked up
+        match run("echo oops >&2") {
+            Err(ProfError::ValgrindFailed(status, stderr)) => {
+                assert!(status.starts_with("exit status 0 and no "));
+                assert_eq!(stderr, "oops");
+            }
+            _ => panic!("missing output was not an error"),
+        }
+        // a program that fails still leaves a complete profile
+        assert_eq!(run("echo ok > $OUT; exit 3").unwrap(), "ok\n");
+        match run("exit 3") {
+            Err(ProfError::ValgrindFailed(status, _)) => {
+                assert!(status.starts_with("exit status 3 and no "))
+            }
+            _ => panic!("missing output was not an error"),
+        }
+        let _ = fs::remove_file(Script("").output_file());
+    }
+
+    #[test]
+    fn test_command() {
+        let command = CallGrind::default().command(
+            "./foo",
+            &[OsStr::new("-x")],
+            &["--fair-sched=yes".to_string()],
+        );
+        let args = command.get_args().collect::<Vec<_>>();
+        assert_eq!(
+            args,
+            vec![
+                "--tool=callgrind",
+                "--callgrind-out-file=callgrind.out",
+                "--fair-sched=yes",
+                "./foo",
+                "-x"
+            ]
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use crate::err::ProfError;
+use crate::report::Report;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Path of the baseline called `name`, inside the `profiler` directory of the cargo target
+/// directory.
+pub fn baseline_path(target_dir: &Path, name: &str) -> PathBuf {
+    target_dir.join("profiler").join(format!("{}.json", name))
+}
+
+/// Store a report as the baseline called `name`.
+pub fn save(report: &Report, name: &str, target_dir: &Path) -> Result<PathBuf, ProfError> {
+    let path = baseline_path(target_dir, name);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let json = serde_json::to_string_pretty(report).expect("could not serialize report");
+    fs::write(&path, json)?;
+    Ok(path)
+}
+
+/// Load the baseline called `name`.
+pub fn load(name: &str, target_dir: &Path) -> Result<Report, ProfError> {
+    let path = baseline_path(target_dir, name);
+    let json =
+        fs::read_to_string(&path).map_err(|_| ProfError::BaselineNotFound(name.to_string()))?;
+    serde_json::from_str(&json).map_err(|_| ProfError::InvalidBaseline(name.to_string()))
+}
+
+/// A function whose cost changed between the baseline and the current run.
+pub struct Change {
+    pub name: String,
+    pub old: f64,
+    pub new: f64,
+}
+
+impl Change {
+    pub fn delta(&self) -> f64 {
+        self.new - self.old
+    }
+
+    /// Relative change in percent, infinite for functions that were free before.
+    pub fn percent(&self) -> f64 {
+        if self.old != 0. {
+            self.delta() / self.old * 100.
+        } else if self.new == 0. {
+            0.
+        } else {
+            f64::INFINITY
+        }
+    }
+}
+
+/// Per-function difference of one metric between a baseline and the current run.
+pub struct Diff {
+    pub baseline: String,
+    pub metric: String,
+    pub total: Change,
+    /// functions in both runs, biggest absolute change first
+    pub changed: Vec<Change>,
+    /// functions only in the current run, most expensive first
+    pub added: Vec<(String, f64)>,
+    /// functions only in the baseline, most expensive first
+    pub removed: Vec<(String, f64)>,
+}
+
+/// `file:function` labels of a report and their cost for `metric`.
+fn costs(report: &Report, metric: &str) -> Vec<(String, f64)> {
+    report
+        .functions
+        .iter()
+        .map(|x| {
+            let cost = x.metrics.get(metric).cloned().unwrap_or(0.);
+            (format!("{}:{}", x.file, x.name), cost)
+        })
+        .collect()
+}
+
+fn sort_by_cost(list: &mut [(String, f64)]) {
+    list.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+}
+
+impl Diff {
+    pub fn new(
+        baseline: &str,
+        old: &Report,
+        new: &Report,
+        metric: &str,
+    ) -> Result<Diff, ProfError> {
+        if old.profiler != new.profiler || !old.events.iter().any(|x| x == metric) {
+            return Err(ProfError::InvalidBaseline(baseline.to_string()));
+        }
+
+        let old_costs = costs(old, metric);
+        let new_costs = costs(new, metric);
+        let old_index = old_costs.iter().cloned().collect::<HashMap<_, _>>();
+        let new_index = new_costs.iter().cloned().collect::<HashMap<_, _>>();
+
+        let mut changed = new_costs
+            .iter()
+            .filter_map(|(name, cost)| {
+                old_index.get(name).map(|&old| Change {
+                    name: name.clone(),
+                    old,
+                    new: *cost,
+                })
+            })
+            .filter(|x| x.delta() != 0.)
+            .collect::<Vec<_>>();
+        changed.sort_by(|a, b| b.delta().abs().partial_cmp(&a.delta().abs()).unwrap());
+
+        let mut added = new_costs
+            .into_iter()
+            .filter(|x| !old_index.contains_key(&x.0))
+            .collect::<Vec<_>>();
+        sort_by_cost(&mut added);
+        let mut removed = old_costs
+            .into_iter()
+            .filter(|x| !new_index.contains_key(&x.0))
+            .collect::<Vec<_>>();
+        sort_by_cost(&mut removed);
+
+        // the total of an event without a program total, like the inclusive cost, is the
+        // sum over all functions
+        let total = |report: &Report| match report.totals.get(metric) {
+            Some(&x) => x,
+            None => costs(report, metric).iter().map(|x| x.1).sum(),
+        };
+
+        Ok(Diff {
+            baseline: baseline.to_string(),
+            metric: metric.to_string(),
+            total: Change {
+                name: "total".to_string(),
+                old: total(old),
+                new: total(new),
+            },
+            changed,
+            added,
+            removed,
+        })
+    }
+
+    /// Keep only the first `num` functions of every list.
+    pub fn truncate(&mut self, num: usize) {
+        self.changed.truncate(num);
+        self.added.truncate(num);
+        self.removed.truncate(num);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{baseline_path, Diff};
+    use crate::report::fixture as report;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_baseline_path() {
+        assert_eq!(
+            baseline_path(Path::new("/tmp/target"), "main"),
+            PathBuf::from("/tmp/target/profiler/main.json")
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = report("events: Ir\nfl=a.rs\nfn=a\n1 100\nfn=b\n1 50\nfn=c\n1 10\nfn=d\n1 5\n");
+        let new = report("events: Ir\nfl=a.rs\nfn=a\n1 90\nfn=b\n1 75\nfn=c\n1 10\nfn=e\n1 20\n");
+        let diff = Diff::new("before", &old, &new, "Ir").unwrap();
+
+        assert_eq!((diff.total.old, diff.total.new), (165., 195.));
+        assert_eq!(diff.changed.len(), 2);
+        assert_eq!(diff.changed[0].name, "a.rs:b");
+        assert_eq!(diff.changed[0].percent(), 50.);
+        assert_eq!(diff.changed[1].delta(), -10.);
+        assert_eq!(diff.added, vec![("a.rs:e".to_string(), 20.)]);
+        assert_eq!(diff.removed, vec![("a.rs:d".to_string(), 5.)]);
+
+        assert!(Diff::new("before", &old, &new, "Dr").is_err());
+    }
+}
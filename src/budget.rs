@@ -0,0 +1,289 @@
+use crate::err::ProfError;
+use crate::report::Report;
+use std::fs;
+
+/// What a budget rule limits.
+#[derive(Debug, PartialEq)]
+pub enum Subject {
+    /// the program total
+    Total,
+    /// every function whose `file:function` label contains the pattern
+    Function(String),
+}
+
+/// Upper bound of a budget rule.
+#[derive(Debug, PartialEq)]
+pub enum Limit {
+    /// absolute cost
+    Absolute(f64),
+    /// growth in percent compared with the baseline
+    Growth(f64),
+}
+
+/// A budget rule such as `total.Ir <= +2%` or `fn(foo::work) <= 1000000`.
+///
+/// The grammar is `SUBJECT[.METRIC] <= LIMIT`, where `SUBJECT` is `total` or
+/// `fn(PATTERN)`, `METRIC` defaults to the metric the results are sorted by, and `LIMIT`
+/// is either an absolute number or a growth like `+2%`. `<` may be used instead of `<=`.
+/// `PATTERN` is taken as is, so it may contain spaces and angle brackets.
+#[derive(Debug, PartialEq)]
+pub struct Rule {
+    pub text: String,
+    pub subject: Subject,
+    pub metric: Option<String>,
+    pub limit: Limit,
+    pub strict: bool,
+}
+
+impl Rule {
+    pub fn parse(text: &str) -> Result<Rule, ProfError> {
+        let invalid = || ProfError::InvalidBudget(text.to_string());
+
+        // the last `<` outside the parentheses, function names may contain `<` themselves
+        let mut depth = 0;
+        let mut comparator = None;
+        for (idx, c) in text.char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                '<' if depth == 0 => comparator = Some(idx),
+                _ => (),
+            }
+        }
+        let idx = comparator.ok_or_else(invalid)?;
+        let lhs = text[..idx].trim();
+        let (rhs, strict) = match text[idx + 1..].strip_prefix('=') {
+            Some(rhs) => (rhs, false),
+            None => (&text[idx + 1..], true),
+        };
+        let rhs = rhs.split_whitespace().collect::<String>();
+
+        let (subject, rest) = if let Some(rest) = lhs.strip_prefix("total") {
+            (Subject::Total, rest)
+        } else if let Some(rest) = lhs.strip_prefix("fn(") {
+            let end = rest.rfind(')').ok_or_else(invalid)?;
+            (
+                Subject::Function(rest[..end].trim().to_string()),
+                &rest[end + 1..],
+            )
+        } else {
+            return Err(invalid());
+        };
+        let rest = rest.split_whitespace().collect::<String>();
+        let metric = match rest.as_str() {
+            "" => None,
+            x => Some(x.strip_prefix('.').ok_or_else(invalid)?.to_string()),
+        };
+
+        let limit = match rhs.strip_suffix('%') {
+            Some(perc) => Limit::Growth(perc.parse().map_err(|_| invalid())?),
+            None => Limit::Absolute(rhs.replace(',', "").parse().map_err(|_| invalid())?),
+        };
+
+        Ok(Rule {
+            text: text.trim().to_string(),
+            subject,
+            metric,
+            limit,
+            strict,
+        })
+    }
+
+    fn allows(&self, value: f64, limit: f64) -> bool {
+        if self.strict {
+            value < limit
+        } else {
+            value <= limit
+        }
+    }
+}
+
+/// Read budget rules from a file with one rule per line. Empty lines and lines starting
+/// with `#` are ignored.
+pub fn read_rules(path: &str) -> Result<Vec<Rule>, ProfError> {
+    fs::read_to_string(path)?
+        .lines()
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty() && !x.starts_with('#'))
+        .map(Rule::parse)
+        .collect()
+}
+
+/// Result of checking a rule for one subject.
+pub struct Outcome {
+    pub rule: String,
+    /// `total` or the label of the function
+    pub subject: String,
+    /// cost or growth in percent, `None` if there was nothing to check
+    pub value: Option<f64>,
+    pub growth: bool,
+    pub passed: bool,
+}
+
+/// Check the rules against a report and, for growth limits, a baseline.
+pub fn check(
+    rules: &[Rule],
+    report: &Report,
+    baseline: Option<&Report>,
+    default_metric: &str,
+) -> Result<Vec<Outcome>, ProfError> {
+    let mut outcomes = Vec::new();
+
+    for rule in rules {
+        let metric = match rule.metric {
+            Some(ref name) => report
+                .events
+                .iter()
+                .find(|x| x.eq_ignore_ascii_case(name))
+                .ok_or_else(|| ProfError::InvalidBudget(rule.text.clone()))?,
+            None => default_metric,
+        };
+        if let Limit::Growth(_) = rule.limit {
+            if baseline.is_none() {
+                return Err(ProfError::InvalidBudget(format!(
+                    "{} (growth limits need --baseline)",
+                    rule.text
+                )));
+            }
+        }
+
+        // the cost of every subject in a report
+        let costs = |report: &Report| -> Vec<(String, f64)> {
+            match rule.subject {
+                Subject::Total => {
+                    let total = match report.totals.get(metric) {
+                        Some(&x) => x,
+                        None => report
+                            .functions
+                            .iter()
+                            .filter_map(|x| x.metrics.get(metric))
+                            .sum(),
+                    };
+                    vec![("total".to_string(), total)]
+                }
+                Subject::Function(ref pattern) => report
+                    .functions
+                    .iter()
+                    .map(|x| (format!("{}:{}", x.file, x.name), x))
+                    .filter(|(label, _)| label.contains(pattern.as_str()))
+                    .map(|(label, x)| (label, x.metrics.get(metric).cloned().unwrap_or(0.)))
+                    .collect(),
+            }
+        };
+
+        // a rule that matches nothing is most likely a typo or a renamed function
+        let current = costs(report);
+        if current.is_empty() {
+            outcomes.push(Outcome {
+                rule: rule.text.clone(),
+                subject: "no matching function".to_string(),
+                value: None,
+                growth: false,
+                passed: false,
+            });
+        }
+
+        for (subject, cost) in current {
+            let (value, growth) = match rule.limit {
+                Limit::Absolute(_) => (Some(cost), false),
+                Limit::Growth(_) => {
+                    let old = baseline
+                        .map(&costs)
+                        .unwrap_or_default()
+                        .into_iter()
+                        .find(|x| x.0 == subject)
+                        .map(|x| x.1);
+                    let growth = match old {
+                        Some(old) if old > 0. => Some((cost - old) / old * 100.),
+                        Some(_) if cost == 0. => Some(0.),
+                        _ => None,
+                    };
+                    (growth, true)
+                }
+            };
+            let limit = match rule.limit {
+                Limit::Absolute(x) | Limit::Growth(x) => x,
+            };
+
+            outcomes.push(Outcome {
+                rule: rule.text.clone(),
+                subject,
+                passed: value.map(|x| rule.allows(x, limit)).unwrap_or(true),
+                value,
+                growth,
+            });
+        }
+    }
+
+    Ok(outcomes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check, Limit, Rule, Subject};
+    use crate::report::fixture as report;
+
+    #[test]
+    fn test_parse_rule() {
+        assert_eq!(
+            Rule::parse("total.Ir <= +2%").unwrap(),
+            Rule {
+                text: "total.Ir <= +2%".to_string(),
+                subject: Subject::Total,
+                metric: Some("Ir".to_string()),
+                limit: Limit::Growth(2.),
+                strict: false,
+            }
+        );
+
+        let rule = Rule::parse("fn(foo::work)<1,000,000").unwrap();
+        assert_eq!(rule.subject, Subject::Function("foo::work".to_string()));
+        assert_eq!(rule.metric, None);
+        assert_eq!(rule.limit, Limit::Absolute(1_000_000.));
+        assert!(rule.strict);
+
+        let rule = Rule::parse("fn( <foo::Cache as Clone>::clone ).Ir <= 5").unwrap();
+        assert_eq!(
+            rule.subject,
+            Subject::Function("<foo::Cache as Clone>::clone".to_string())
+        );
+        assert_eq!(rule.metric, Some("Ir".to_string()));
+        assert_eq!(rule.limit, Limit::Absolute(5.));
+        assert!(!rule.strict);
+        let rule = Rule::parse("fn(Vec<u8> as Drop) < 5").unwrap();
+        assert_eq!(
+            rule.subject,
+            Subject::Function("Vec<u8> as Drop".to_string())
+        );
+        assert!(rule.strict);
+
+        assert!(Rule::parse("total >= 5").is_err());
+        assert!(Rule::parse("main <= 5").is_err());
+        assert!(Rule::parse("total.Ir <= lots").is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let old = report("events: Ir\nfl=a.rs\nfn=a\n1 100\nfn=b\n1 100\n");
+        let new = report("events: Ir\nfl=a.rs\nfn=a\n1 103\nfn=b\n1 100\n");
+        let rules = vec![
+            Rule::parse("total <= +2%").unwrap(),
+            Rule::parse("fn(a.rs:a) <= 103").unwrap(),
+            Rule::parse("fn(a.rs:) < 103").unwrap(),
+            Rule::parse("fn(nothing) <= 0").unwrap(),
+        ];
+        let outcomes = check(&rules, &new, Some(&old), "Ir").unwrap();
+
+        assert_eq!(outcomes.len(), 5);
+        assert_eq!(outcomes[0].value, Some(1.5));
+        assert!(outcomes[0].passed);
+        assert!(outcomes[1].passed);
+        assert!(!outcomes[2].passed);
+        assert!(outcomes[3].passed);
+        assert!(!outcomes[4].passed && outcomes[4].value.is_none());
+
+        assert!(check(&rules, &new, None, "Ir").is_err());
+        let rules = vec![Rule::parse("total.Dr <= 10").unwrap()];
+        assert!(check(&rules, &new, None, "Ir").is_err());
+    }
+}
@@ -0,0 +1,235 @@
+use crate::backend::{Backend, Results};
+use crate::cargo::{build_binary, target_directory, BuildOptions, Target};
+use crate::err::ProfError;
+use crate::report::{Metadata, Report};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::PathBuf;
+
+/// Builds, runs and parses a profile.
+///
+/// ```no_run
+/// use cargo_profiler::backend::CallGrind;
+/// use cargo_profiler::Builder;
+///
+/// let profile = Builder::new(CallGrind::default())
+///     .binary("target/release/foo")
+///     .arg("--input=big.txt")
+///     .num(10)
+///     .run()?;
+/// println!("{}", profile.results);
+/// # Ok::<(), cargo_profiler::ProfError>(())
+/// ```
+#[derive(Debug)]
+pub struct Builder {
+    backend: Box<dyn Backend>,
+    binary: Option<String>,
+    build: BuildOptions,
+    args: Vec<OsString>,
+    valgrind_args: Vec<String>,
+    num: usize,
+    keep_output: bool,
+}
+
+/// A profiled run.
+pub struct Profile {
+    /// path of the profiled binary
+    pub binary: String,
+    /// arguments the binary was run with
+    pub args: Vec<String>,
+    /// extra arguments valgrind was run with
+    pub valgrind_args: Vec<String>,
+    /// raw profile written by valgrind
+    pub output: String,
+    /// the first `num` functions
+    pub results: Box<dyn Results>,
+    /// all functions
+    pub all: Box<dyn Results>,
+}
+
+impl Builder {
+    pub fn new<B: Backend + 'static>(backend: B) -> Builder {
+        Builder::with_backend(Box::new(backend))
+    }
+
+    /// Profile with a backend of the registry.
+    pub fn with_backend(backend: Box<dyn Backend>) -> Builder {
+        Builder {
+            backend,
+            binary: None,
+            build: BuildOptions::default(),
+            args: Vec::new(),
+            valgrind_args: Vec::new(),
+            num: 10000, // some arbitrarily large number...
+            keep_output: false,
+        }
+    }
+
+    /// Profile an already built binary instead of building one with cargo.
+    pub fn binary(mut self, path: &str) -> Builder {
+        self.binary = Some(path.to_string());
+        self
+    }
+
+    /// Build the binary to profile with cargo.
+    pub fn build(mut self, options: BuildOptions) -> Builder {
+        self.build = options;
+        self
+    }
+
+    /// Append an argument for the profiled binary.
+    pub fn arg<S: AsRef<OsStr>>(mut self, arg: S) -> Builder {
+        self.args.push(arg.as_ref().to_os_string());
+        self
+    }
+
+    /// Append arguments for the profiled binary.
+    pub fn args<I, S>(mut self, args: I) -> Builder
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.args
+            .extend(args.into_iter().map(|x| x.as_ref().to_os_string()));
+        self
+    }
+
+    /// Append arguments for valgrind itself, e.g. `--fair-sched=yes`.
+    pub fn valgrind_args<I, S>(mut self, args: I) -> Builder
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.valgrind_args
+            .extend(args.into_iter().map(|x| x.as_ref().to_string()));
+        self
+    }
+
+    /// Number of functions to keep in `Profile::results`.
+    pub fn num(mut self, num: usize) -> Builder {
+        self.num = num;
+        self
+    }
+
+    /// Keep the profile valgrind wrote in the current directory.
+    pub fn keep_output(mut self, keep: bool) -> Builder {
+        self.keep_output = keep;
+        self
+    }
+
+    /// Path of the binary to profile, building it first if none was given.
+    pub fn binary_path(&self) -> Result<String, ProfError> {
+        match self.binary {
+            Some(ref path) => Ok(path.clone()),
+            None => build_binary(&self.build),
+        }
+    }
+
+    /// Directory cargo builds into, where baselines are kept too. Falls back to `target` in
+    /// the current directory outside of a cargo project, e.g. for a prebuilt `binary`.
+    pub fn target_directory(&self) -> PathBuf {
+        target_directory(&self.build).unwrap_or_else(|_| PathBuf::from("target"))
+    }
+
+    /// Parse a profile, keeping the first `num` functions.
+    pub fn parse(&self, output: &str, num: usize) -> Result<Box<dyn Results>, ProfError> {
+        self.backend.parse(output, num)
+    }
+
+    /// Run valgrind on `binary` and parse the profile.
+    pub fn run_binary(&self, binary: &str) -> Result<Profile, ProfError> {
+        let mut args = self.args.iter().map(|x| x.as_os_str()).collect::<Vec<_>>();
+        // bench harnesses only run benchmarks when asked to, like cargo bench does
+        if self.binary.is_none() {
+            if let Target::Bench(_) = self.build.target {
+                args.insert(0, OsStr::new("--bench"));
+            }
+        }
+
+        let output = self.backend.run(binary, &args, &self.valgrind_args);
+        if !self.keep_output {
+            let _ = fs::remove_file(self.backend.output_file());
+        }
+        let output = output?;
+
+        let results = self.parse(&output, self.num)?;
+        let all = self.parse(&output, usize::MAX)?;
+
+        Ok(Profile {
+            binary: binary.to_string(),
+            args: args
+                .iter()
+                .map(|x| x.to_string_lossy().into_owned())
+                .collect(),
+            valgrind_args: self.valgrind_args.clone(),
+            output,
+            results,
+            all,
+        })
+    }
+
+    /// The backend the profile is taken with.
+    pub fn backend(&self) -> &dyn Backend {
+        &*self.backend
+    }
+
+    /// Build the binary if needed, then profile it.
+    pub fn run(&self) -> Result<Profile, ProfError> {
+        self.run_binary(&self.binary_path()?)
+    }
+}
+
+impl Profile {
+    /// Information about the run.
+    pub fn metadata(&self) -> Metadata {
+        Metadata::new(&self.binary, &self.args).with_valgrind(&self.valgrind_args, &self.output)
+    }
+
+    /// Report of the first `num` functions.
+    pub fn report(&self) -> Report {
+        self.results.report(self.metadata())
+    }
+
+    /// Metric the functions are sorted by, e.g. `Ir` or `D1mr`.
+    pub fn metric(&self) -> &str {
+        self.all.metric()
+    }
+
+    /// Report of all functions, as used for baselines and budgets.
+    pub fn full_report(&self) -> Report {
+        self.all.report(self.metadata())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Builder;
+    use crate::backend::{CacheGrind, CallGrind};
+    use crate::profiler::CacheGrindResults;
+
+    #[test]
+    fn test_builder_parse() {
+        let output = "events: Ir\nfl=a.rs\nfn=a::x::h0000000000000001\n1 3\nfn=a::y::h0000000000000002\n2 9\nfn=a::z::h0000000000000003\n3 1\n";
+        let builder = Builder::new(CacheGrind {
+            sort: Some("ir".to_string()),
+            ..CacheGrind::default()
+        })
+        .num(2);
+        let results = builder.parse(output, 2).unwrap();
+        assert!(results.graph().is_none());
+        let results = results
+            .as_any()
+            .downcast_ref::<CacheGrindResults>()
+            .unwrap();
+        assert_eq!(results.functs, vec!["a.rs:a::y", "a.rs:a::x"]);
+        assert_eq!(results.metric, "Ir");
+        assert!(Builder::new(CacheGrind {
+            sort: Some("dr".to_string()),
+            ..CacheGrind::default()
+        })
+        .parse(output, 2)
+        .is_err());
+        let results = Builder::new(CallGrind::default()).parse(output, 2).unwrap();
+        assert!(results.graph().is_some());
+    }
+}
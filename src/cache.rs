@@ -0,0 +1,72 @@
+use crate::err::ProfError;
+use std::collections::BTreeMap;
+
+/// Cache geometries of common CPUs, as `(name, I1, D1, LL)`. Every cache is given as
+/// `size,associativity,line size` in bytes, the way valgrind's `--I1`, `--D1` and `--LL`
+/// options take them.
+pub const CACHE_PRESETS: [(&str, &str, &str, &str); 4] = [
+    ("skylake", "32768,8,64", "32768,8,64", "8388608,16,64"),
+    ("zen3", "32768,8,64", "32768,8,64", "33554432,16,64"),
+    ("neoverse-n1", "65536,4,64", "65536,4,64", "1048576,8,64"),
+    ("cortex-a72", "49152,3,64", "32768,2,64", "1048576,16,64"),
+];
+
+/// Valgrind arguments that simulate the caches of the preset called `name`.
+pub fn preset_args(name: &str) -> Result<Vec<String>, ProfError> {
+    let (_, i1, d1, ll) = CACHE_PRESETS
+        .iter()
+        .find(|x| x.0 == name)
+        .ok_or_else(|| ProfError::InvalidCacheConfig(name.to_string()))?;
+
+    Ok(vec![
+        "--cache-sim=yes".to_string(),
+        format!("--I1={}", i1),
+        format!("--D1={}", d1),
+        format!("--LL={}", ll),
+    ])
+}
+
+/// The simulated cache geometry from the `desc:` lines of a cachegrind or callgrind
+/// profile, e.g. `LL` => `8388608 B, 64 B, 16-way associative`.
+pub fn geometry(output: &str) -> BTreeMap<String, String> {
+    output
+        .lines()
+        .take_while(|x| !x.starts_with("fl="))
+        .filter_map(|x| x.strip_prefix("desc:"))
+        .filter_map(|x| {
+            let idx = x.find(" cache:")?;
+            Some((x[..idx].trim().to_string(), x[idx + 7..].trim().to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{geometry, preset_args};
+
+    #[test]
+    fn test_preset_args() {
+        assert_eq!(
+            preset_args("skylake").unwrap(),
+            vec![
+                "--cache-sim=yes",
+                "--I1=32768,8,64",
+                "--D1=32768,8,64",
+                "--LL=8388608,16,64"
+            ]
+        );
+        assert!(preset_args("pentium").is_err());
+    }
+
+    #[test]
+    fn test_geometry() {
+        let output = "desc: I1 cache:         32768 B, 64 B, 8-way associative\n\
+                      desc: D1 cache:         32768 B, 64 B, 8-way associative\n\
+                      desc: LL cache:         8388608 B, 64 B, 16-way associative\n\
+                      cmd: ./foo\nevents: Ir\nfl=a.rs\nfn=a\n1 1\n";
+        let geometry = geometry(output);
+        assert_eq!(geometry.len(), 3);
+        assert_eq!(geometry["LL"], "8388608 B, 64 B, 16-way associative");
+        assert!(super::geometry("events: Ir\n").is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+/// A function in the call graph.
+pub struct Node {
+    pub name: String,
+    /// cost spent in the function itself
+    pub self_cost: f64,
+    /// cost spent in the function and everything it calls
+    pub inclusive: f64,
+}
+
+/// A caller -> callee edge, summed over all call sites.
+pub struct Edge {
+    pub caller: usize,
+    pub callee: usize,
+    /// number of times the call was executed
+    pub calls: u64,
+    /// cost spent in the callee on behalf of this caller
+    pub inclusive: f64,
+}
+
+/// Call graph built from the `fn=`/`cfn=`/`calls=` records of a callgrind profile.
+#[derive(Default)]
+pub struct CallGraph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+    node_index: HashMap<String, usize>,
+    edge_index: HashMap<(usize, usize), usize>,
+}
+
+impl CallGraph {
+    /// Index of the node called `name`, which is added if it doesn't exist yet.
+    pub fn node(&mut self, name: &str) -> usize {
+        if let Some(&idx) = self.node_index.get(name) {
+            return idx;
+        }
+        self.nodes.push(Node {
+            name: name.to_string(),
+            self_cost: 0.,
+            inclusive: 0.,
+        });
+        self.node_index
+            .insert(name.to_string(), self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    /// Attribute `cost` to the node itself.
+    pub fn add_self(&mut self, node: usize, cost: f64) {
+        self.nodes[node].self_cost += cost;
+        self.nodes[node].inclusive += cost;
+    }
+
+    /// Record `calls` calls from `caller` to `callee` which together cost `cost`.
+    pub fn add_call(&mut self, caller: usize, callee: usize, calls: u64, cost: f64) {
+        let edges = &mut self.edges;
+        let idx = *self.edge_index.entry((caller, callee)).or_insert_with(|| {
+            edges.push(Edge {
+                caller,
+                callee,
+                calls: 0,
+                inclusive: 0.,
+            });
+            edges.len() - 1
+        });
+        self.edges[idx].calls += calls;
+        self.edges[idx].inclusive += cost;
+        // recursive calls are already part of the caller's inclusive cost
+        if caller != callee {
+            self.nodes[caller].inclusive += cost;
+        }
+    }
+
+    /// Total self cost of all nodes.
+    pub fn total(&self) -> f64 {
+        self.nodes.iter().map(|x| x.self_cost).sum()
+    }
+
+    /// Indices of all nodes whose name contains `pattern`.
+    pub fn find(&self, pattern: &str) -> Vec<usize> {
+        (0..self.nodes.len())
+            .filter(|&x| self.nodes[x].name.contains(pattern))
+            .collect()
+    }
+
+    /// Edges into `node`, most expensive first.
+    pub fn callers(&self, node: usize) -> Vec<&Edge> {
+        self.sorted_edges(|x| x.callee == node)
+    }
+
+    /// Edges out of `node`, most expensive first.
+    pub fn callees(&self, node: usize) -> Vec<&Edge> {
+        self.sorted_edges(|x| x.caller == node)
+    }
+
+    fn sorted_edges<F: Fn(&Edge) -> bool>(&self, pred: F) -> Vec<&Edge> {
+        let mut edges = self.edges.iter().filter(|x| pred(x)).collect::<Vec<_>>();
+        edges.sort_by(|a, b| b.inclusive.partial_cmp(&a.inclusive).unwrap());
+        edges
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CallGraph;
+
+    #[test]
+    fn test_add_call() {
+        let mut graph = CallGraph::default();
+        let main = graph.node("main");
+        let fib = graph.node("fib");
+        graph.add_self(main, 5.);
+        graph.add_self(fib, 60.);
+        graph.add_call(main, fib, 1, 100.);
+        graph.add_call(fib, fib, 4, 30.);
+        graph.add_call(fib, fib, 4, 10.);
+
+        assert_eq!(graph.node("fib"), fib);
+        assert_eq!(graph.total(), 65.);
+        assert_eq!(graph.nodes[main].inclusive, 105.);
+        assert_eq!(graph.nodes[fib].inclusive, 60.);
+        assert_eq!(graph.edges.len(), 2);
+        assert_eq!(graph.callees(fib)[0].calls, 8);
+        assert_eq!(graph.callers(fib).len(), 2);
+        assert_eq!(graph.callers(fib)[0].caller, main);
+    }
+
+    #[test]
+    fn test_find() {
+        let mut graph = CallGraph::default();
+        graph.node("main.rs:foo::main");
+        graph.node("main.rs:foo::work");
+        graph.node("memset.S:memset");
+        assert_eq!(graph.find("foo::"), vec![0, 1]);
+        assert!(graph.find("bar").is_empty());
+    }
+}
@@ -1,128 +1,458 @@
 use crate::err::ProfError;
-use serde_json::Value;
+use serde::Deserialize;
 use std::env;
-use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
 
-/// Returns the closest ancestor path containing a `target` directory.
-///
-/// Returns `None` if no ancestor path contains a `target` directory, or if
-/// the limit of MAX_ANCESTORS ancestors has been reached.
-pub fn find_target() -> Option<PathBuf> {
-    /// Checks if the directory contains `Cargo.toml`
-    fn contains_manifest(path: &PathBuf) -> bool {
-        fs::read_dir(path)
-            .map(|entries| {
-                entries
-                    .filter_map(|res| res.ok())
-                    .any(|ent| ent.path().ends_with("target"))
-            })
-            .unwrap_or(false)
+/// Which target of a package to build and profile.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Target {
+    /// the only binary of the package, or its `default-run` binary
+    #[default]
+    Default,
+    Bin(String),
+    Example(String),
+    /// integration test harness, compiled with `cargo test --no-run`
+    Test(String),
+    /// benchmark harness, compiled with `cargo bench --no-run`
+    Bench(String),
+}
+
+/// How to build the binary to profile.
+#[derive(Clone, Debug, Default)]
+pub struct BuildOptions {
+    /// workspace member to build, defaults to the package in the current directory
+    pub package: Option<String>,
+    pub target: Target,
+    pub release: bool,
+    /// custom cargo profile, e.g. `bench-lto`
+    pub profile: Option<String>,
+    pub features: Vec<String>,
+    pub no_default_features: bool,
+    /// target triple to build for
+    pub target_triple: Option<String>,
+    pub target_dir: Option<String>,
+    /// keep the debug info setting of optimized profiles instead of forcing it on
+    pub no_debug_info: bool,
+    /// tell on stderr what is being compiled
+    pub verbose: bool,
+}
+
+// the parts of `cargo metadata` and `cargo build --message-format=json` we need
+#[derive(Deserialize)]
+struct Workspace {
+    packages: Vec<Package>,
+    workspace_members: Vec<String>,
+    target_directory: PathBuf,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct Package {
+    name: String,
+    id: String,
+    manifest_path: PathBuf,
+    targets: Vec<CargoTarget>,
+    default_run: Option<String>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct CargoTarget {
+    name: String,
+    kind: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    reason: String,
+    package_id: Option<String>,
+    target: Option<CargoTarget>,
+    executable: Option<String>,
+}
+
+/// A target that was picked to be built, with the cargo flag that selects it.
+#[derive(Debug, PartialEq)]
+struct Selected<'a> {
+    package: &'a Package,
+    kind: &'static str,
+    name: String,
+}
+
+impl Package {
+    fn has_target(&self, kind: &str, name: &str) -> bool {
+        self.targets
+            .iter()
+            .any(|x| x.name == name && x.kind.iter().any(|k| k == kind))
     }
 
-    // From the current directory we work our way up, looking for `Cargo.toml`
-    env::current_dir().ok().and_then(|mut wd| {
-        for _ in 0..10 {
-            if contains_manifest(&wd) {
-                return Some(wd);
-            }
-            if !wd.pop() {
-                break;
+    fn binaries(&self) -> Vec<&str> {
+        self.targets
+            .iter()
+            .filter(|x| x.kind.iter().any(|k| k == "bin"))
+            .map(|x| x.name.as_str())
+            .collect()
+    }
+}
+
+// read the workspace layout with cargo metadata
+fn metadata() -> Result<Workspace, ProfError> {
+    let out = Command::new("cargo")
+        .args(["metadata", "--no-deps", "--format-version", "1"])
+        .output()
+        .map_err(|e| ProfError::CargoError(e.to_string()))?;
+
+    if !out.status.success() {
+        return Err(ProfError::ReadManifestError);
+    }
+    serde_json::from_slice(&out.stdout).or(Err(ProfError::ReadManifestError))
+}
+
+/// Directory cargo writes its build output to: `--target-dir` if given, otherwise the one
+/// `cargo metadata` reports, which honours `CARGO_TARGET_DIR` and `build.target-dir`.
+pub fn target_directory(options: &BuildOptions) -> Result<PathBuf, ProfError> {
+    match options.target_dir {
+        Some(ref dir) => Ok(PathBuf::from(dir)),
+        None => Ok(metadata()?.target_directory),
+    }
+}
+
+// pick the package and target to build. Without -p, the package whose directory
+// contains `cwd` is used, or all workspace members if there is none (e.g. at the root
+// of a virtual workspace).
+fn select<'a>(
+    workspace: &'a Workspace,
+    options: &BuildOptions,
+    cwd: &Path,
+) -> Result<Selected<'a>, ProfError> {
+    let members = workspace
+        .packages
+        .iter()
+        .filter(|x| workspace.workspace_members.contains(&x.id))
+        .collect::<Vec<_>>();
+
+    let candidates = match options.package {
+        Some(ref name) => vec![*members
+            .iter()
+            .find(|x| &x.name == name)
+            .ok_or_else(|| ProfError::UnknownPackage(name.clone()))?],
+        None => {
+            let current = members
+                .iter()
+                .filter(|x| x.manifest_path.parent().is_some_and(|x| cwd.starts_with(x)))
+                .max_by_key(|x| x.manifest_path.as_os_str().len());
+            match current {
+                Some(package) => vec![*package],
+                None => members,
             }
         }
+    };
+
+    let (kind, name) = match options.target {
+        Target::Bin(ref name) => ("bin", name),
+        Target::Example(ref name) => ("example", name),
+        Target::Test(ref name) => ("test", name),
+        Target::Bench(ref name) => ("bench", name),
+        Target::Default => {
+            let binaries = candidates
+                .iter()
+                .flat_map(|x| {
+                    let binaries = match x.default_run {
+                        Some(ref name) => vec![name.as_str()],
+                        None => x.binaries(),
+                    };
+                    binaries.into_iter().map(move |name| (*x, name))
+                })
+                .collect::<Vec<_>>();
+
+            return match binaries[..] {
+                [(package, name)] => Ok(Selected {
+                    package,
+                    kind: "bin",
+                    name: name.to_string(),
+                }),
+                _ => Err(ProfError::AmbiguousTarget(
+                    binaries
+                        .iter()
+                        .map(|(package, name)| format!("{}/{}", package.name, name))
+                        .collect(),
+                )),
+            };
+        }
+    };
 
-        None
-    })
+    let packages = candidates
+        .into_iter()
+        .filter(|x| x.has_target(kind, name))
+        .collect::<Vec<_>>();
+    match packages[..] {
+        [package] => Ok(Selected {
+            package,
+            kind,
+            name: name.clone(),
+        }),
+        [] => Err(ProfError::UnknownTarget(kind.to_string(), name.clone())),
+        _ => Err(ProfError::AmbiguousTarget(
+            packages
+                .iter()
+                .map(|x| format!("{}/{}", x.name, name))
+                .collect(),
+        )),
+    }
 }
 
-// returns the name of the package parsed from Cargo.toml
-// this will only work if the package name is directly underneath [package] tag
-pub fn get_package_name() -> Result<String, ProfError> {
-    let manifest = Command::new("cargo")
-        .arg("read-manifest")
-        .output()
-        .unwrap_or_else(|e| panic!("failed to execute process: {}", e));
+// find the executable cargo built for the selected target in its JSON messages
+fn find_artifact(messages: &str, selected: &Selected) -> Option<String> {
+    messages
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Message>(line).ok())
+        .filter(|x| x.reason == "compiler-artifact")
+        .filter(|x| x.package_id.as_ref() == Some(&selected.package.id))
+        .filter(|x| {
+            x.target.as_ref().is_some_and(|t| {
+                t.name == selected.name && t.kind.iter().any(|k| k == selected.kind)
+            })
+        })
+        .filter_map(|x| x.executable)
+        .next_back()
+}
 
-    let out = String::from_utf8(manifest.stdout).unwrap_or_default();
-    let data: Value = serde_json::from_str(&out).or(Err(ProfError::ReadManifestError))?;
+// cargo config overriding the debug info settings of a cargo profile
+fn debug_info_config(profile: &str) -> Vec<String> {
+    // bench inherits from release, so both have to be set
+    let profiles = match profile {
+        "bench" => vec!["release", "bench"],
+        x => vec![x],
+    };
+    profiles
+        .into_iter()
+        .flat_map(|x| {
+            vec![
+                format!("profile.{}.debug=true", x),
+                format!("profile.{}.strip=false", x),
+            ]
+        })
+        .collect()
+}
 
-    data.as_object()
-        .expect("Could not extract object from read manifest JSON. Please submit bug.")
-        .get("name")
-        .ok_or(ProfError::NoNameError)
-        .and_then(|x| Ok(x.to_string().replace("\"", "")))
+// name of the cargo profile the binary is built with
+fn profile(options: &BuildOptions) -> &str {
+    match (&options.profile, &options.target) {
+        (Some(profile), _) => profile,
+        (None, Target::Bench(_)) => "bench",
+        (None, _) if options.release => "release",
+        _ => "dev",
+    }
+}
+
+// arguments to cargo for building the selected target
+fn build_args(options: &BuildOptions, selected: &Selected) -> Vec<String> {
+    // tests and benchmarks are only compiled by their own cargo commands
+    let subcommand = match options.target {
+        Target::Test(_) => "test",
+        Target::Bench(_) => "bench",
+        _ => "build",
+    };
+    let mut args = vec![
+        subcommand.to_string(),
+        "--message-format=json-render-diagnostics".to_string(),
+        "--package".to_string(),
+        selected.package.name.clone(),
+        format!("--{}", selected.kind),
+        selected.name.clone(),
+    ];
+    if subcommand != "build" {
+        args.push("--no-run".to_string());
+    }
+    if let Some(ref profile) = options.profile {
+        args.extend(vec!["--profile".to_string(), profile.clone()]);
+    } else if options.release && subcommand != "bench" {
+        args.push("--release".to_string());
+    }
+    for features in options.features.iter() {
+        args.extend(vec!["--features".to_string(), features.clone()]);
+    }
+    if options.no_default_features {
+        args.push("--no-default-features".to_string());
+    }
+    if let Some(ref triple) = options.target_triple {
+        args.extend(vec!["--target".to_string(), triple.clone()]);
+    }
+    if let Some(ref dir) = options.target_dir {
+        args.extend(vec!["--target-dir".to_string(), dir.clone()]);
+    }
+    // optimized profiles come without debug info, which leaves valgrind with `???`
+    // instead of function names. Turn it on without touching Cargo.toml. `dev` and
+    // `--profile test`, which inherits from it, have debug info already.
+    let profile = profile(options);
+    if !matches!(profile, "dev" | "test") && !options.no_debug_info {
+        for config in debug_info_config(profile) {
+            args.extend(vec!["--config".to_string(), config]);
+        }
+    }
+    args
 }
 
 // build the binary by calling cargo build
 // return the path to the built binary
-pub fn build_binary(release: bool) -> Result<String, ProfError> {
-    let package_name = get_package_name()?;
+pub fn build_binary(options: &BuildOptions) -> Result<String, ProfError> {
+    let workspace = metadata()?;
+    let cwd = env::current_dir()?;
+    let selected = select(&workspace, options, &cwd)?;
 
-    let (out, binary_dir) = if release {
-        println!(
-            "\n\x1b[1;33mCompiling \x1b[1;0m{} in release mode...",
-            package_name
-        );
-
-        (
-            Command::new("cargo")
-                .args(&["build", "--release"])
-                .output()
-                .unwrap_or_else(|e| panic!("failed to execute process: {}", e)),
-            "/target/release/",
-        )
-    } else {
-        println!(
-            "\n\x1b[1;33mCompiling \x1b[1;0m{} in debug mode...",
-            package_name
+    if options.verbose {
+        let profile = profile(options);
+        eprintln!(
+            "\n\x1b[1;33mCompiling \x1b[1;0m{} in {} mode...",
+            selected.name,
+            if profile == "dev" { "debug" } else { profile }
         );
+    }
 
-        (
-            Command::new("cargo")
-                .arg("build")
-                .output()
-                .unwrap_or_else(|e| panic!("failed to execute process: {}", e)),
-            "/target/debug/",
-        )
-    };
+    let out = Command::new("cargo")
+        .args(build_args(options, &selected))
+        .output()
+        .map_err(|e| ProfError::CargoError(e.to_string()))?;
 
-    let target_dir = find_target()
-        .ok_or(ProfError::NoTargetDirectory)
-        .and_then(|x| {
-            Ok(x.to_str()
-                .expect("target directory could not be converted to string.")
-                .to_string())
-        });
-    let path = target_dir
-        .and_then(|x| Ok(x + binary_dir + &package_name))
-        .unwrap_or_default();
-
-    if !Path::new(&path).exists() {
-        return Err(ProfError::CompilationError(
-            package_name.to_string(),
+    let messages = String::from_utf8(out.stdout).unwrap_or_default();
+    match find_artifact(&messages, &selected) {
+        Some(path) if out.status.success() && Path::new(&path).exists() => Ok(path),
+        _ => Err(ProfError::CompilationError(
+            selected.name,
             String::from_utf8(out.stderr).unwrap_or_default(),
-        ));
+        )),
     }
-    Ok(path)
 }
 
 #[cfg(test)]
 mod test {
+    use super::{
+        build_args, debug_info_config, find_artifact, profile, select, BuildOptions, Target,
+        Workspace,
+    };
+    use std::path::Path;
+
+    const METADATA: &str = r#"{
+        "packages": [
+            {"name": "a", "id": "a 0.1.0", "manifest_path": "/ws/a/Cargo.toml",
+             "default_run": null,
+             "targets": [{"name": "a", "kind": ["bin"]}]},
+            {"name": "b", "id": "b 0.1.0", "manifest_path": "/ws/b/Cargo.toml",
+             "default_run": null,
+             "targets": [{"name": "b", "kind": ["lib"]},
+                         {"name": "tool", "kind": ["bin"]},
+                         {"name": "demo", "kind": ["example"]},
+                         {"name": "it", "kind": ["test"]},
+                         {"name": "speed", "kind": ["bench"]}]}
+        ],
+        "workspace_members": ["a 0.1.0", "b 0.1.0"],
+        "target_directory": "/ws/target"
+    }"#;
+
+    fn options(package: Option<&str>, target: Target) -> BuildOptions {
+        BuildOptions {
+            package: package.map(|x| x.to_string()),
+            target,
+            ..Default::default()
+        }
+    }
+
     #[test]
-    fn test_find_target() {
-        assert_eq!(1, 1);
+    fn test_select() {
+        let workspace: Workspace = serde_json::from_str(METADATA).unwrap();
+
+        let root = Path::new("/ws");
+        assert!(select(&workspace, &options(None, Target::Default), root).is_err());
+        let selected = select(&workspace, &options(Some("b"), Target::Default), root).unwrap();
+        assert_eq!(
+            (selected.package.name.as_str(), selected.name.as_str()),
+            ("b", "tool")
+        );
+
+        let src = Path::new("/ws/a/src");
+        let selected = select(&workspace, &options(None, Target::Default), src).unwrap();
+        assert_eq!(selected.package.name, "a");
+
+        let example = Target::Example("demo".to_string());
+        let selected = select(&workspace, &options(None, example), root).unwrap();
+        assert_eq!(
+            (selected.package.name.as_str(), selected.kind),
+            ("b", "example")
+        );
+
+        let test = Target::Test("it".to_string());
+        let selected = select(&workspace, &options(None, test), root).unwrap();
+        assert_eq!(
+            (selected.package.name.as_str(), selected.kind),
+            ("b", "test")
+        );
+        let bench = Target::Bench("speed".to_string());
+        assert_eq!(
+            select(&workspace, &options(None, bench), root)
+                .unwrap()
+                .kind,
+            "bench"
+        );
+
+        let bin = Target::Bin("tool".to_string());
+        assert!(select(&workspace, &options(Some("a"), bin), root).is_err());
+        assert!(select(&workspace, &options(Some("c"), Target::Default), root).is_err());
     }
 
     #[test]
-    fn test_get_package_name() {
-        assert_eq!(1, 1);
-        assert_eq!(1, 1);
+    fn test_find_artifact() {
+        let workspace: Workspace = serde_json::from_str(METADATA).unwrap();
+        let selected = select(
+            &workspace,
+            &options(Some("b"), Target::Default),
+            Path::new("/"),
+        )
+        .unwrap();
+        let messages = r#"{"reason":"compiler-artifact","package_id":"b 0.1.0","target":{"name":"b","kind":["lib"]},"executable":null}
+{"reason":"compiler-artifact","package_id":"b 0.1.0","target":{"name":"tool","kind":["bin"]},"executable":"/tmp/target/debug/tool"}
+{"reason":"build-finished","success":true}"#;
+
+        assert_eq!(
+            find_artifact(messages, &selected),
+            Some("/tmp/target/debug/tool".to_string())
+        );
+        assert_eq!(find_artifact("", &selected), None);
+    }
+
+    #[test]
+    fn test_build_args() {
+        let workspace: Workspace = serde_json::from_str(METADATA).unwrap();
+        let mut options = options(Some("b"), Target::Bench("speed".to_string()));
+        options.release = true;
+        let selected = select(&workspace, &options, Path::new("/")).unwrap();
+        assert_eq!(profile(&options), "bench");
+        assert_eq!(
+            build_args(&options, &selected).join(" "),
+            "bench --message-format=json-render-diagnostics --package b --bench speed --no-run \
+             --config profile.release.debug=true --config profile.release.strip=false \
+             --config profile.bench.debug=true --config profile.bench.strip=false"
+        );
+
+        options.profile = Some("test".to_string());
+        assert!(!build_args(&options, &selected).contains(&"--config".to_string()));
+
+        options.profile = Some("bench-lto".to_string());
+        options.features = vec!["simd".to_string()];
+        options.no_default_features = true;
+        options.target_triple = Some("x86_64-unknown-linux-musl".to_string());
+        options.no_debug_info = true;
+        assert_eq!(profile(&options), "bench-lto");
+        assert!(build_args(&options, &selected).join(" ").ends_with(
+            "--no-run --profile bench-lto --features simd --no-default-features \
+             --target x86_64-unknown-linux-musl"
+        ));
     }
 
     #[test]
-    fn test_build_binary() {
-        assert_eq!(1, 1);
+    fn test_debug_info_config() {
+        assert_eq!(
+            debug_info_config("release"),
+            vec!["profile.release.debug=true", "profile.release.strip=false"]
+        );
+        assert_eq!(debug_info_config("bench").len(), 4);
     }
 }
@@ -0,0 +1,84 @@
+use std::convert::TryInto;
+use std::fs;
+
+// read an unsigned integer of `size` bytes at `offset`
+fn read(data: &[u8], offset: usize, size: usize, little_endian: bool) -> Option<u64> {
+    let bytes = data.get(offset..offset.checked_add(size)?)?;
+    Some(match (size, little_endian) {
+        (2, true) => u16::from_le_bytes(bytes.try_into().ok()?) as u64,
+        (2, false) => u16::from_be_bytes(bytes.try_into().ok()?) as u64,
+        (4, true) => u32::from_le_bytes(bytes.try_into().ok()?) as u64,
+        (4, false) => u32::from_be_bytes(bytes.try_into().ok()?) as u64,
+        (8, true) => u64::from_le_bytes(bytes.try_into().ok()?),
+        (8, false) => u64::from_be_bytes(bytes.try_into().ok()?),
+        _ => return None,
+    })
+}
+
+/// Names of the sections of an ELF file, or `None` if `data` is no ELF file.
+pub fn section_names(data: &[u8]) -> Option<Vec<String>> {
+    if data.get(..4)? != b"\x7fELF" {
+        return None;
+    }
+    let le = *data.get(5)? == 1;
+    // offsets of the header fields and the size of addresses for 32 and 64 bit files
+    let (shoff, shentsize, word) = match *data.get(4)? {
+        1 => (0x20, 0x2e, 4),
+        2 => (0x28, 0x3a, 8),
+        _ => return None,
+    };
+
+    let shoff = read(data, shoff, word, le)? as usize;
+    let entsize = read(data, shentsize, 2, le)? as usize;
+    let shnum = read(data, shentsize + 2, 2, le)? as usize;
+    let shstrndx = read(data, shentsize + 4, 2, le)? as usize;
+
+    // sh_offset and sh_size of a section header
+    let section = |idx: usize| -> Option<(usize, usize)> {
+        let header = shoff.checked_add(idx.checked_mul(entsize)?)?;
+        let offset = read(data, header + 8 + 2 * word, word, le)? as usize;
+        let size = read(data, header + 8 + 3 * word, word, le)? as usize;
+        Some((offset, size))
+    };
+    let (strtab, strtab_size) = section(shstrndx)?;
+    let strtab = data.get(strtab..strtab.checked_add(strtab_size)?)?;
+
+    (0..shnum)
+        .map(|idx| {
+            let name = read(data, shoff + idx * entsize, 4, le)? as usize;
+            let name = strtab.get(name..)?;
+            let end = name.iter().position(|&x| x == 0)?;
+            Some(String::from_utf8_lossy(&name[..end]).into_owned())
+        })
+        .collect()
+}
+
+/// Whether the binary at `path` has DWARF debug info. `None` if that can't be told,
+/// e.g. because it is no ELF file.
+pub fn has_debug_info(path: &str) -> Option<bool> {
+    let data = fs::read(path).ok()?;
+    let names = section_names(&data)?;
+    Some(
+        names
+            .iter()
+            .any(|x| x == ".debug_info" || x == ".zdebug_info"),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{has_debug_info, section_names};
+    use std::env;
+
+    #[test]
+    fn test_section_names() {
+        assert_eq!(section_names(b"#!/bin/sh\n"), None);
+        assert_eq!(section_names(b"\x7fELF\x02\x01"), None);
+
+        // the test binary itself is built with debug info
+        let exe = env::current_exe().unwrap();
+        if cfg!(target_os = "linux") {
+            assert_eq!(has_debug_info(exe.to_str().unwrap()), Some(true));
+        }
+    }
+}
@@ -1,10 +1,19 @@
-use crate::profiler::Profiler;
+use crate::baseline::Diff;
+use crate::budget::Outcome;
+use crate::callgraph::CallGraph;
+use crate::parse::cachegrind::CYCLES;
+use crate::parse::collapse_generics;
+use crate::parse::memcheck::LEAK_KINDS;
+use crate::profiler::{
+    CacheGrindResults, CallGrindResults, DhatResults, Frame, MassifResults, MemcheckResults,
+    RaceResults, Snapshot, Stack, ValgrindError,
+};
+use crate::report::{crate_name, split_label};
 use ndarray::Axis;
 use std::fmt;
 
 // pretty-print separator of functions
-static DASHES: &'static str =
-    "-----------------------------------------------------------------------";
+static DASHES: &str = "-----------------------------------------------------------------------";
 
 /// Format a number with thousands separators. copied from cargo bench.
 fn fmt_thousands_sep(n: f64, sep: char) -> String {
@@ -36,129 +45,743 @@ fn fmt_thousands_sep(n: f64, sep: char) -> String {
     output
 }
 
-/// Pretty-print the profiler outputs into user-friendly formats.
-impl fmt::Display for Profiler {
+/// A function or label as printed: with `{:#}` its generic arguments are collapsed to `<…>`.
+fn fmt_name(f: &fmt::Formatter, name: &str) -> String {
+    if f.alternate() {
+        collapse_generics(name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// The label with its first character in upper case, e.g. `Definitely lost`.
+fn capitalize(label: &str) -> String {
+    let mut chars = label.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Fraction of `total` taken up by `x`. Events that were not collected have a total of
+/// zero, which we show as zero instead of NaN.
+fn ratio(x: f64, total: f64) -> f64 {
+    if total > 0. {
+        x / total
+    } else {
+        0.
+    }
+}
+
+/// Format a percentage, colored red from 50%, yellow from 30% and green below that.
+fn fmt_perc(perc: f64) -> String {
+    match perc {
+        t if t >= 50.0 => format!("\x1b[31m{:.1}%\x1b[0m", t),
+        t if (30.0..50.0).contains(&t) => format!("\x1b[33m{:.1}%\x1b[0m", t),
+        t => format!("\x1b[32m{:.1}%\x1b[0m", t),
+    }
+}
+
+/// Pretty-print cachegrind results into a user-friendly format.
+impl fmt::Display for CacheGrindResults {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            Profiler::CacheGrind {
-                ref ir,
-                ref i1mr,
-                ref ilmr,
-                ref dr,
-                ref d1mr,
-                ref dlmr,
-                ref dw,
-                ref d1mw,
-                ref dlmw,
-                ref data,
-                ref functs,
-            } => {
+        let CacheGrindResults {
+            ref events,
+            ref totals,
+            ref data,
+            ref functs,
+            ..
+        } = *self;
+        // sum of the totals of those events that were counted
+        let total = |names: &[&str]| {
+            names
+                .iter()
+                .filter_map(|name| events.iter().position(|x| x == name))
+                .map(|idx| totals[idx])
+                .fold(None, |sum, x| Some(sum.unwrap_or(0.) + x))
+        };
+
+        let accesses = total(&["Ir", "Dr", "Dw"]).unwrap_or(0.);
+        let _ = writeln!(
+            f,
+            "\n\x1b[32mTotal Memory Accesses\x1b[0m...{}\t\x1b[0m",
+            fmt_thousands_sep(accesses, ',')
+        );
+        if let Some(cycles) = total(&[CYCLES]) {
+            let _ = writeln!(
+                f,
+                "\x1b[32mEstimated Cycles\x1b[0m...{}\t\x1b[0m",
+                fmt_thousands_sep(cycles, ',')
+            );
+        }
+        let misses = [
+            ("Total L1 I-Cache Misses", &["I1mr"][..]),
+            ("Total LL I-Cache Misses", &["ILmr"][..]),
+            ("Total L1 D-Cache Misses", &["D1mr", "D1mw"][..]),
+            ("Total LL D-Cache Misses", &["DLmr", "DLmw"][..]),
+        ];
+        for (title, names) in misses.iter() {
+            if let Some(x) = total(names) {
                 let _ = write!(
                     f,
-                    "\n\x1b[32mTotal Memory Accesses\x1b[0m...{}\t\x1b[0m\n\
-                       \n\x1b[32mTotal L1 I-Cache Misses\x1b[0m...{} ({}%)\t\x1b[0m\
-                       \n\x1b[32mTotal LL I-Cache Misses\x1b[0m...{} ({}%)\t\x1b[0m\
-                       \n\x1b[32mTotal L1 D-Cache Misses\x1b[0m...{} ({}%)\t\x1b[0m\
-                        \n\x1b[32mTotal LL D-Cache Misses\x1b[0m...{} ({}%)\t\x1b[0m\n\n\
-
-                    ",
-                    fmt_thousands_sep(ir + dr + dw, ','),
-                    fmt_thousands_sep(*i1mr, ','),
-                    fmt_thousands_sep(i1mr / (ir + dr + dw) * 100., ','),
-                    fmt_thousands_sep(*ilmr, ','),
-                    fmt_thousands_sep(ilmr / (ir + dr + dw) * 100., ','),
-                    fmt_thousands_sep(d1mr + d1mw, ','),
-                    fmt_thousands_sep((d1mr + d1mw) / (ir + dr + dw) * 100., ','),
-                    fmt_thousands_sep(dlmr + dlmw, ','),
-                    fmt_thousands_sep((dlmr + dlmw) / (ir + dr + dw) * 100., ','),
+                    "\n\x1b[32m{}\x1b[0m...{} ({}%)\t\x1b[0m",
+                    title,
+                    fmt_thousands_sep(x, ','),
+                    fmt_thousands_sep(ratio(x, accesses) * 100., ','),
                 );
+            }
+        }
+        let _ = writeln!(f);
+
+        // branch counters are only there with --branch-sim
+        let branches = [
+            ("Total Conditional Branches", "Bc", "Bcm"),
+            ("Total Indirect Branches", "Bi", "Bim"),
+        ];
+        for (title, executed, mispredicted) in branches.iter() {
+            if let (Some(x), Some(y)) = (total(&[executed]), total(&[mispredicted])) {
                 let _ = writeln!(
                     f,
-                    " \x1b[1;36mIr  \x1b[1;36mI1mr \x1b[1;36mILmr  \x1b[1;36mDr  \
-                     \x1b[1;36mD1mr \x1b[1;36mDLmr  \x1b[1;36mDw  \x1b[1;36mD1mw \
-                     \x1b[1;36mDLmw"
+                    "\x1b[32m{}\x1b[0m...{} ({:.2}% mispredicted)",
+                    title,
+                    fmt_thousands_sep(x, ','),
+                    ratio(y, x) * 100.,
                 );
+            }
+        }
+        let _ = writeln!(f);
 
-                for (ref x, y) in data.axis_iter(Axis(0)).zip(functs.iter()) {
-                    let _ = writeln!(
-                        f,
-                        "\x1b[0m{:.2} {:.2} {:.2} {:.2} {:.2} {:.2} {:.2} {:.2} {:.2} \
-                         {}",
-                        x[0] / ir,
-                        x[1] / i1mr,
-                        x[2] / ilmr,
-                        x[3] / dr,
-                        x[4] / d1mr,
-                        x[5] / dlmr,
-                        x[6] / dw,
-                        x[7] / d1mw,
-                        x[8] / dlmw,
-                        y
-                    );
-                    println!("{}", DASHES);
-                }
-                Ok(())
+        // one column per event with the share of the event's total
+        let widths = events.iter().map(|x| x.len().max(4)).collect::<Vec<_>>();
+        for (event, width) in events.iter().zip(widths.iter()) {
+            let _ = write!(f, "\x1b[1;36m{:<w$} ", event, w = width);
+        }
+        let _ = writeln!(f, "\x1b[0m");
+
+        for (ref x, y) in data.axis_iter(Axis(0)).zip(functs.iter()) {
+            for ((count, total), width) in x.iter().zip(totals.iter()).zip(widths.iter()) {
+                let _ = write!(f, "{:<w$.2} ", ratio(*count, *total), w = width);
             }
+            let y = fmt_name(f, y);
+            let _ = writeln!(f, "{}", y);
+            let _ = writeln!(f, "{}", DASHES);
+        }
+        Ok(())
+    }
+}
 
-            Profiler::CallGrind {
-                ref total_instructions,
-                ref instructions,
-                ref functs,
-            } => {
-                let _ = write!(
+/// Pretty-print callgrind results into a user-friendly format.
+impl fmt::Display for CallGrindResults {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let CallGrindResults {
+            ref total_instructions,
+            ref instructions,
+            ref inclusive,
+            ref functs,
+            ..
+        } = *self;
+        let _ = write!(
+            f,
+            "\n\x1b[32mTotal Instructions\x1b[0m...{}\n\n\x1b[0m",
+            fmt_thousands_sep(*total_instructions, ',')
+        );
+        let _ = writeln!(f, "\x1b[1;36mSelf (%) Inclusive (%) Function\x1b[0m");
+
+        for ((&x, &incl), y) in instructions.iter().zip(inclusive.iter()).zip(functs) {
+            let y = fmt_name(f, y);
+            let _ = writeln!(
+                f,
+                "{} ({}) {} ({}) {}",
+                fmt_thousands_sep(x, ','),
+                fmt_perc(ratio(x, *total_instructions) * 100.),
+                fmt_thousands_sep(incl, ','),
+                fmt_perc(ratio(incl, *total_instructions) * 100.),
+                y
+            );
+            let _ = writeln!(f, "{}", DASHES);
+        }
+        Ok(())
+    }
+}
+
+/// Format a byte count with a binary unit, e.g. `1.50 MiB`.
+fn fmt_bytes(n: f64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut n = n;
+    let mut unit = 0;
+    while n >= 1024. && unit < units.len() - 1 {
+        n /= 1024.;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", n)
+    } else {
+        format!("{:.2} {}", n, units[unit])
+    }
+}
+
+/// Text chart of the heap over time, `height` rows by `width` columns. Every snapshot is
+/// a bar in the column of its time; like ms_print, the peak is drawn with `#`, detailed
+/// snapshots with `@` and the others with `:`.
+pub fn heap_chart(
+    snapshots: &[Snapshot],
+    peak: usize,
+    time_unit: &str,
+    width: usize,
+    height: usize,
+) -> String {
+    let max_time = snapshots.iter().map(|x| x.time).fold(0., f64::max);
+    let max_heap = snapshots.iter().map(|x| x.heap).fold(0., f64::max);
+
+    // height and symbol of the bar in every column
+    let mut bars: Vec<Option<(usize, char)>> = vec![None; width];
+    for (idx, snapshot) in snapshots.iter().enumerate() {
+        let column = if max_time > 0. {
+            (snapshot.time / max_time * (width - 1) as f64).round() as usize
+        } else {
+            0
+        };
+        let bar = (ratio(snapshot.heap, max_heap) * height as f64).round() as usize;
+        let symbol = match idx {
+            x if x == peak => '#',
+            _ if snapshot.detailed => '@',
+            _ => ':',
+        };
+        match bars[column] {
+            Some((_, '#')) => {}
+            Some((old, _)) if old > bar && symbol != '#' => {}
+            _ => bars[column] = Some((bar, symbol)),
+        }
+    }
+
+    let label = fmt_bytes(max_heap);
+    let pad = label.len().max(1);
+    let mut out = String::new();
+    for row in (1..=height).rev() {
+        let axis = if row == height { label.as_str() } else { "" };
+        out.push_str(&format!("{:>w$} |", axis, w = pad));
+        for bar in bars.iter() {
+            out.push(match *bar {
+                Some((x, symbol)) if x >= row => symbol,
+                _ => ' ',
+            });
+        }
+        out.push('\n');
+    }
+    out.push_str(&format!("{:>w$} +{}>\n", 0, "-".repeat(width), w = pad));
+    let end = format!("{} {}", fmt_thousands_sep(max_time, ','), time_unit);
+    out.push_str(&format!(
+        "{:>w$}  0{:>e$}\n",
+        "",
+        end,
+        w = pad,
+        e = width.saturating_sub(1).max(end.len() + 1)
+    ));
+    out
+}
+
+/// Pretty-print the peak heap, its allocation sites and a chart of the heap over time.
+impl fmt::Display for MassifResults {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let peak = &self.snapshots[self.peak];
+        let _ = writeln!(
+            f,
+            "\n\x1b[32mPeak Heap\x1b[0m...{} ({} B) at {} {}",
+            fmt_bytes(peak.heap),
+            fmt_thousands_sep(peak.heap, ','),
+            fmt_thousands_sep(peak.time, ','),
+            self.time_unit
+        );
+        let _ = writeln!(f, "\x1b[32mHeap Extra\x1b[0m...{}", fmt_bytes(peak.extra));
+        if peak.stacks > 0. {
+            let _ = writeln!(f, "\x1b[32mStacks\x1b[0m...{}", fmt_bytes(peak.stacks));
+        }
+        let _ = writeln!(
+            f,
+            "\n{}",
+            heap_chart(&self.snapshots, self.peak, &self.time_unit, 72, 16)
+        );
+
+        let _ = writeln!(f, "\x1b[1;36mBytes at Peak (%) Allocation Site\x1b[0m");
+        for (&x, y) in self.bytes.iter().zip(self.functs.iter()) {
+            let y = fmt_name(f, y);
+            let _ = writeln!(
+                f,
+                "{} ({}) {}",
+                fmt_thousands_sep(x, ','),
+                fmt_perc(ratio(x, peak.heap) * 100.),
+                y
+            );
+            let _ = writeln!(f, "{}", DASHES);
+        }
+        Ok(())
+    }
+}
+
+/// Pretty-print the allocation sites of a dhat profile, grouped by the crate they are in.
+/// Crates are listed in the order of their first site.
+impl fmt::Display for DhatResults {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let _ = writeln!(
+            f,
+            "\n\x1b[32mTotal Allocated\x1b[0m...{} in {} blocks",
+            fmt_bytes(self.total_bytes),
+            fmt_thousands_sep(self.total_blocks, ',')
+        );
+        let _ = writeln!(
+            f,
+            "\x1b[32mAt Global Peak\x1b[0m...{}",
+            fmt_bytes(self.peak_bytes)
+        );
+
+        let mut crates: Vec<&str> = Vec::new();
+        for site in self.sites.iter() {
+            let krate = crate_name(split_label(&site.funct).1);
+            if !crates.contains(&krate) {
+                crates.push(krate);
+            }
+        }
+
+        let fmt_access = |x: Option<f64>| x.map_or("-".to_string(), |x| format!("{:.2}", x));
+        for krate in crates {
+            let sites = self
+                .sites
+                .iter()
+                .filter(|x| crate_name(split_label(&x.funct).1) == krate)
+                .collect::<Vec<_>>();
+            let bytes = sites.iter().map(|x| x.bytes).sum::<f64>();
+            let _ = writeln!(
+                f,
+                "\n\x1b[1;33mCrate {}\x1b[0m {} ({})\n",
+                if krate.is_empty() { "???" } else { krate },
+                fmt_bytes(bytes),
+                fmt_perc(ratio(bytes, self.total_bytes) * 100.)
+            );
+            let _ = writeln!(
+                f,
+                "\x1b[1;36mBytes (%) Blocks Avg Lifetime Reads/B Writes/B Site\x1b[0m"
+            );
+            for site in sites {
+                let metrics = site.metrics();
+                let funct = fmt_name(f, &site.funct);
+                let _ = writeln!(
                     f,
-                    "\n\x1b[32mTotal Instructions\x1b[0m...{}\n\n\x1b[0m",
-                    fmt_thousands_sep(*total_instructions, ',')
+                    "{} ({}) {} {} {} {} {}",
+                    fmt_thousands_sep(site.bytes, ','),
+                    fmt_perc(ratio(site.bytes, self.total_bytes) * 100.),
+                    fmt_thousands_sep(site.blocks, ','),
+                    fmt_thousands_sep(metrics[2].unwrap_or(0.), ','),
+                    fmt_access(metrics[3]),
+                    fmt_access(metrics[4]),
+                    funct
                 );
+                let _ = writeln!(f, "{}", DASHES);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `function (file:line)`, or `function (in object)` for code without debug info.
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let basename = |x: &str| x.rsplit('/').next().unwrap_or("").to_string();
+        let func = fmt_name(f, &self.func);
+        match (&self.file, self.line, &self.obj) {
+            (Some(file), Some(line), _) => write!(f, "{} ({}:{})", func, basename(file), line),
+            (Some(file), None, _) => write!(f, "{} ({})", func, basename(file)),
+            (None, _, Some(obj)) => write!(f, "{} (in {})", func, obj),
+            (None, _, None) => write!(f, "{}", func),
+        }
+    }
+}
 
-                for (&x, ref y) in instructions.iter().zip(functs.iter()) {
-                    {
-                        let perc = x / total_instructions * 100.;
-                        match perc {
-                            t if t >= 50.0 => {
-                                let _ = writeln!(
-                                    f,
-                                    "{} (\x1b[31m{:.1}%\x1b[0m)\x1b[0m {}",
-                                    fmt_thousands_sep(x, ','),
-                                    t,
-                                    y
-                                );
-                                println!("{}", DASHES);
-                            }
-                            t if (t >= 30.0) & (t < 50.0) => {
-                                let _ = writeln!(
-                                    f,
-                                    "{} (\x1b[33m{:.1}%\x1b[0m)\x1b[0m {}",
-                                    fmt_thousands_sep(x, ','),
-                                    t,
-                                    y
-                                );
-                                println!("{}", DASHES);
-                            }
-                            _ => {
-                                let _ = writeln!(
-                                    f,
-                                    "{} (\x1b[32m{:.1}%\x1b[0m)\x1b[0m {}",
-                                    fmt_thousands_sep(x, ','),
-                                    x / total_instructions * 100.,
-                                    y
-                                );
-                                println!("{}", DASHES);
-                            }
-                        }
-                    }
+/// The title of a stack and its frames, innermost first, like valgrind prints them.
+impl fmt::Display for Stack {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let _ = writeln!(f, "{}", self.title);
+        for (idx, frame) in self.frames.iter().enumerate() {
+            let _ = write!(f, "   {} ", if idx == 0 { "at" } else { "by" });
+            let _ = frame.fmt(f);
+            let _ = writeln!(f);
+        }
+        Ok(())
+    }
+}
+
+/// The kind of an error, how often it occurred and all its stacks.
+impl fmt::Display for ValgrindError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let _ = write!(f, "\x1b[1;33m[{}]\x1b[0m", self.kind);
+        if self.count > 1 {
+            let _ = write!(f, " x{}", self.count);
+        }
+        let _ = writeln!(f);
+        for (idx, stack) in self.stacks.iter().enumerate() {
+            if idx > 0 && !stack.frames.is_empty() {
+                let _ = writeln!(f);
+            }
+            let _ = stack.fmt(f);
+        }
+        let _ = writeln!(f, "{}", DASHES);
+        Ok(())
+    }
+}
+
+/// Pretty-print a summary of the errors and leaks, the crates they happened in and every
+/// error with its stacks. Crates with `unsafe` code are highlighted, it is the first place
+/// to look for the cause.
+impl fmt::Display for MemcheckResults {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let _ = writeln!(
+            f,
+            "\n{}Errors\x1b[0m...{}",
+            if self.failures > 0 {
+                "\x1b[1;31m"
+            } else {
+                "\x1b[32m"
+            },
+            fmt_thousands_sep(self.failures as f64, ',')
+        );
+        for (kind, bytes, blocks) in self.leaks.iter() {
+            let label = LEAK_KINDS
+                .iter()
+                .find(|x| x.0 == kind)
+                .map_or(&kind[..], |x| x.1);
+            let _ = writeln!(
+                f,
+                "\x1b[32m{}\x1b[0m...{} in {} blocks",
+                capitalize(label),
+                fmt_bytes(*bytes),
+                fmt_thousands_sep(*blocks, ',')
+            );
+        }
+
+        if !self.crates.is_empty() {
+            let _ = writeln!(f, "\n\x1b[1;36mErrors Unsafe Crate\x1b[0m");
+            for krate in self.crates.iter() {
+                let line = format!(
+                    "{} {} {}",
+                    fmt_thousands_sep(krate.errors as f64, ','),
+                    krate.unsafe_count,
+                    krate.name
+                );
+                if krate.unsafe_count > 0 {
+                    let _ = writeln!(f, "\x1b[1;31m{}\x1b[0m", line);
+                } else {
+                    let _ = writeln!(f, "{}", line);
                 }
-                Ok(())
             }
         }
+
+        let _ = writeln!(f);
+        for error in self.errors.iter() {
+            let _ = error.fmt(f);
+        }
+        Ok(())
+    }
+}
+
+/// Pretty-print the distinct race reports of helgrind or drd, each with the stack of the
+/// access and those of the access or segment it conflicts with.
+impl fmt::Display for RaceResults {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let _ = writeln!(
+            f,
+            "\n{}Errors\x1b[0m...{} in {} distinct reports",
+            if self.failures > 0 {
+                "\x1b[1;31m"
+            } else {
+                "\x1b[32m"
+            },
+            fmt_thousands_sep(self.failures as f64, ','),
+            fmt_thousands_sep(self.distinct as f64, ',')
+        );
+        let _ = writeln!(f);
+        for error in self.errors.iter() {
+            let _ = error.fmt(f);
+        }
+        Ok(())
+    }
+}
+
+/// Callers and callees of the functions matching a pattern.
+pub struct Focus<'a> {
+    pub graph: &'a CallGraph,
+    pub pattern: &'a str,
+}
+
+/// Pretty-print who calls the focused functions and what they call.
+impl<'a> fmt::Display for Focus<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let graph = self.graph;
+        let total = graph.total();
+        let matches = graph.find(self.pattern);
+        if matches.is_empty() {
+            return writeln!(f, "\nNo function matching {} was called.", self.pattern);
+        }
+
+        for node in matches {
+            let name = fmt_name(f, &graph.nodes[node].name);
+            let _ = writeln!(
+                f,
+                "\n\x1b[1;33m{}\x1b[0m\nself {} ({}) inclusive {} ({})\n",
+                name,
+                fmt_thousands_sep(graph.nodes[node].self_cost, ','),
+                fmt_perc(ratio(graph.nodes[node].self_cost, total) * 100.),
+                fmt_thousands_sep(graph.nodes[node].inclusive, ','),
+                fmt_perc(ratio(graph.nodes[node].inclusive, total) * 100.),
+            );
+
+            let _ = writeln!(f, "\x1b[1;36mCalled by\x1b[0m");
+            for edge in graph.callers(node) {
+                let caller = fmt_name(f, &graph.nodes[edge.caller].name);
+                let _ = writeln!(
+                    f,
+                    "  {} ({}) {} calls from {}",
+                    fmt_thousands_sep(edge.inclusive, ','),
+                    fmt_perc(ratio(edge.inclusive, total) * 100.),
+                    fmt_thousands_sep(edge.calls as f64, ','),
+                    caller
+                );
+            }
+
+            let _ = writeln!(f, "\x1b[1;36mCalls\x1b[0m");
+            for edge in graph.callees(node) {
+                let callee = fmt_name(f, &graph.nodes[edge.callee].name);
+                let _ = writeln!(
+                    f,
+                    "  {} ({}) {} calls to {}",
+                    fmt_thousands_sep(edge.inclusive, ','),
+                    fmt_perc(ratio(edge.inclusive, total) * 100.),
+                    fmt_thousands_sep(edge.calls as f64, ','),
+                    callee
+                );
+            }
+            let _ = writeln!(f, "{}", DASHES);
+        }
+        Ok(())
+    }
+}
+
+/// Format a signed number with thousands separators and an explicit sign.
+fn fmt_signed(n: f64) -> String {
+    let sign = if n < 0. { '-' } else { '+' };
+    format!("{}{}", sign, fmt_thousands_sep(n.abs(), ','))
+}
+
+/// Format a change in percent, red for increases and green for decreases.
+fn fmt_change(perc: f64) -> String {
+    match perc {
+        t if t.is_infinite() => "\x1b[31mnew\x1b[0m".to_string(),
+        t if t > 0. => format!("\x1b[31m+{:.1}%\x1b[0m", t),
+        t => format!("\x1b[32m{:.1}%\x1b[0m", t),
+    }
+}
+
+/// Pretty-print the difference between a baseline and the current run.
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let _ = writeln!(
+            f,
+            "\n\x1b[1;33mComparing {} with baseline \x1b[1;0m{}\x1b[0m\n\n\
+             \x1b[32mTotal\x1b[0m...{} -> {} ({} {})\n",
+            self.metric,
+            self.baseline,
+            fmt_thousands_sep(self.total.old, ','),
+            fmt_thousands_sep(self.total.new, ','),
+            fmt_signed(self.total.delta()),
+            fmt_change(self.total.percent()),
+        );
+
+        let _ = writeln!(f, "\x1b[1;36mChanged functions\x1b[0m");
+        for change in self.changed.iter() {
+            let _ = writeln!(
+                f,
+                "{} ({}) {} -> {} {}",
+                fmt_signed(change.delta()),
+                fmt_change(change.percent()),
+                fmt_thousands_sep(change.old, ','),
+                fmt_thousands_sep(change.new, ','),
+                change.name
+            );
+        }
+
+        let _ = writeln!(f, "\n\x1b[1;36mNew functions\x1b[0m");
+        for &(ref name, cost) in self.added.iter() {
+            let _ = writeln!(f, "{} {}", fmt_thousands_sep(cost, ','), name);
+        }
+
+        let _ = writeln!(f, "\n\x1b[1;36mVanished functions\x1b[0m");
+        for &(ref name, cost) in self.removed.iter() {
+            let _ = writeln!(f, "{} {}", fmt_thousands_sep(cost, ','), name);
+        }
+        Ok(())
+    }
+}
+
+/// Pretty-print whether a subject stayed within a budget rule.
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = if self.passed {
+            "\x1b[32mok  \x1b[0m"
+        } else {
+            "\x1b[31mFAIL\x1b[0m"
+        };
+        let value = match self.value {
+            None => "nothing to compare".to_string(),
+            Some(x) if self.growth => format!("{:+.2}%", x),
+            Some(x) => fmt_thousands_sep(x, ','),
+        };
+        write!(f, "{} {} ({}: {})", status, self.rule, self.subject, value)
     }
 }
 
 #[cfg(test)]
 mod test {
+    use super::{capitalize, fmt_bytes, heap_chart};
+    use crate::parse::cachegrind::{parse, Latencies};
+    use crate::profiler::{
+        AllocationSite, CrateErrors, DhatResults, Frame, MemcheckResults, Snapshot, Stack,
+        ValgrindError,
+    };
+    use crate::report::GroupBy;
+
+    #[test]
+    fn test_capitalize() {
+        assert_eq!(capitalize("definitely lost"), "Definitely lost");
+        assert_eq!(capitalize("élan"), "Élan");
+        assert_eq!(capitalize(""), "");
+    }
+
     #[test]
     fn test_fmt_thousands_sep() {
         assert_eq!(1, 1);
     }
 
+    #[test]
+    fn test_branch_display() {
+        let output =
+            "events: Ir Bc Bcm Bi Bim\nfl=a.rs\nfn=a::x::h0000000000000001\n1 10 4 1 2 2\n";
+        let results = parse(output, 10, None, &Latencies::default(), GroupBy::Function).unwrap();
+        let text = results.to_string();
+        assert!(text.contains("Conditional Branches\x1b[0m...4 (25.00% mispredicted)"));
+        assert!(text.contains("1.00 1.00 1.00 1.00 a.rs:a::x"));
+
+        let output = "events: Ir\nfl=a.rs\nfn=a::x::h0000000000000001\n1 10\n";
+        let results = parse(output, 10, None, &Latencies::default(), GroupBy::Function).unwrap();
+        assert!(!results.to_string().contains("Branches"));
+    }
+
+    #[test]
+    fn test_collapse_display() {
+        let output = "events: Ir\nfl=vec.rs\n\
+                      fn=_ZN5alloc3vec16Vec$LT$T$C$A$GT$4push17h0123456789abcdefE\n1 10\n";
+        let results = parse(output, 10, None, &Latencies::default(), GroupBy::Function).unwrap();
+        assert!(results
+            .to_string()
+            .contains("vec.rs:alloc::vec::Vec<T,A>::push\n"));
+        assert!(format!("{:#}", results).contains("vec.rs:alloc::vec::Vec<…>::push\n"));
+    }
+
+    #[test]
+    fn test_heap_chart() {
+        assert_eq!(fmt_bytes(512.), "512 B");
+        assert_eq!(fmt_bytes(1536.), "1.50 KiB");
+
+        let snapshot = |time, heap, detailed| Snapshot {
+            time,
+            heap,
+            detailed,
+            ..Snapshot::default()
+        };
+        let snapshots = [
+            snapshot(0., 0., false),
+            snapshot(5., 1024., true),
+            snapshot(10., 2048., true),
+            snapshot(20., 512., false),
+        ];
+        let chart = heap_chart(&snapshots, 2, "i", 5, 4);
+        assert_eq!(
+            chart,
+            "2.00 KiB |  #  \n         |  #  \n         | @#  \n         | @# :\n\
+             \x20      0 +----->\n          0 20 i\n"
+        );
+    }
+
+    #[test]
+    fn test_dhat_display() {
+        let site = |funct: &str, bytes| AllocationSite {
+            funct: funct.to_string(),
+            bytes,
+            blocks: 1.,
+            ..AllocationSite::default()
+        };
+        let results = DhatResults {
+            time_unit: "instrs".to_string(),
+            total_bytes: 100.,
+            total_blocks: 3.,
+            peak_bytes: 10.,
+            sites: vec![
+                site("lib.rs:bar::parse", 50.),
+                site("main.rs:foo::main", 30.),
+                site("lib.rs:bar::lex", 20.),
+            ],
+            metric: "total_B".to_string(),
+        };
+        let text = results.to_string();
+        let bar = text.find("Crate bar\x1b[0m 70 B").unwrap();
+        let foo = text.find("Crate foo\x1b[0m 30 B").unwrap();
+        assert!(bar < foo);
+        assert!(text[bar..foo].contains("bar::lex"));
+        assert!(text.contains("20 (\x1b[32m20.0%\x1b[0m) 1 0 - - lib.rs:bar::lex"));
+    }
+
+    #[test]
+    fn test_memcheck_display() {
+        let frame = |func: &str, file: Option<&str>, line| Frame {
+            func: func.to_string(),
+            file: file.map(|x| x.to_string()),
+            line,
+            obj: Some("/usr/lib/libc.so.6".to_string()),
+        };
+        let results = MemcheckResults {
+            errors: vec![ValgrindError {
+                kind: "InvalidRead".to_string(),
+                stacks: vec![
+                    Stack {
+                        title: "Invalid read of size 4".to_string(),
+                        frames: vec![
+                            frame("foo::read", Some("/src/foo/src/ffi.rs"), Some(12)),
+                            frame("memcpy", None, None),
+                        ],
+                    },
+                    Stack {
+                        title: "Address 0x0 is not stack'd".to_string(),
+                        frames: vec![],
+                    },
+                ],
+                count: 3,
+                ..ValgrindError::default()
+            }],
+            failures: 3,
+            leaks: vec![("Leak_PossiblyLost".to_string(), 2048., 2.)],
+            crates: vec![CrateErrors {
+                name: "foo".to_string(),
+                errors: 3,
+                unsafe_count: 4,
+            }],
+        };
+        let text = results.to_string();
+        assert!(text.contains("\x1b[1;31mErrors\x1b[0m...3\n"));
+        assert!(text.contains("Possibly lost\x1b[0m...2.00 KiB in 2 blocks"));
+        assert!(text.contains("\x1b[1;31m3 4 foo\x1b[0m"));
+        assert!(text.contains(
+            "[InvalidRead]\x1b[0m x3\n\
+             Invalid read of size 4\n   \
+             at foo::read (ffi.rs:12)\n   \
+             by memcpy (in /usr/lib/libc.so.6)\n\
+             Address 0x0 is not stack'd\n"
+        ));
+    }
 }
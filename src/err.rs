@@ -6,21 +6,39 @@ use std::io::Error as ioError;
 /// Represents potential errors that may occur when profiling
 pub enum ProfError {
     RegexError,
-    InvalidProfiler,
+    /// with the names of the profilers there are
+    InvalidProfiler(Vec<String>),
     InvalidBinary,
     InvalidNum,
-    InvalidSortMetric,
+    InvalidSortMetric(String, Vec<String>),
+    InvalidOutputFormat,
     /// Wraps a std::io::Error
     IOError(ioError),
     UTF8Error,
     MisalignedData,
+    /// The profiler output could not be parsed
+    MalformedOutput(String),
     CompilationError(String, String),
-    TomlError,
     ReadManifestError,
-    NoNameError,
-    NoTargetDirectory,
     OutOfMemoryError,
     CliError,
+    BaselineNotFound(String),
+    InvalidBaseline(String),
+    InvalidBudget(String),
+    BudgetExceeded(usize),
+    UnknownPackage(String),
+    UnknownTarget(String, String),
+    AmbiguousTarget(Vec<String>),
+    InvalidCacheConfig(String),
+    InvalidLatencies(String),
+    ValgrindErrors(usize),
+    InvalidGroupBy(String),
+    /// valgrind wrote no results, with how it exited and the end of its stderr
+    ValgrindFailed(String, String),
+    /// `--focus` or `--flamegraph` with a backend that records no call graph
+    NoCallGraph(String),
+    /// cargo could not be started
+    CargoError(String),
 }
 
 impl fmt::Display for ProfError {
@@ -32,10 +50,11 @@ impl fmt::Display for ProfError {
                  please include the original output file from profiler, e.g. from \
                  valgrind --tool=cachegrind --cachegrind-out-file=cachegrind.txt"
             ),
-            ProfError::InvalidProfiler => write!(
+            ProfError::InvalidProfiler(ref names) => write!(
                 f,
                 "\x1b[1;31merror: \x1b[0mInvalid profiler. cargo profiler currently \
-                 supports callgrind and cachegrind."
+                 supports {}.",
+                names.join(", ")
             ),
             ProfError::InvalidBinary => write!(
                 f,
@@ -46,11 +65,17 @@ impl fmt::Display for ProfError {
                 "\x1b[1;31merror: \x1b[0mInvalid number. make sure number is a positive \
                  integer."
             ),
-            ProfError::InvalidSortMetric => write!(
+            ProfError::InvalidSortMetric(ref metric, ref events) => write!(
                 f,
-                "\x1b[1;31merror: \x1b[0mInvalid metric to sort on. available cachegrind \
-                 metrics are \nir, i1mr, ilmr, dr, d1mr, dlmr, dw, d1mw, and dlmw. Check \
-                 README for details on these metrics."
+                "\x1b[1;31merror: \x1b[0mInvalid metric {} to sort on. available metrics \
+                 of this profile are {}. Check README for details on these metrics.",
+                metric,
+                events.join(", ")
+            ),
+            ProfError::InvalidOutputFormat => write!(
+                f,
+                "\x1b[1;31merror: \x1b[0mInvalid output format. available formats are text, \
+                 json, csv and tsv."
             ),
             ProfError::IOError(ref err) => write!(
                 f,
@@ -66,35 +91,22 @@ impl fmt::Display for ProfError {
                 "\x1b[1;31merror: \x1b[0mMisaligned data arrays due to regex error -- \
                  please file a bug."
             ),
+            ProfError::MalformedOutput(ref reason) => write!(
+                f,
+                "\x1b[1;31merror: \x1b[0mCould not parse profiler output ({}) -- please \
+                 file a bug. In bug report, please include the profiler output file.",
+                reason
+            ),
             ProfError::CompilationError(ref package_name, ref stderr) => write!(
                 f,
                 "\x1b[1;31merror: \x1b[0mFailed to compile {}.\n\n{}",
                 package_name, stderr
             ),
-            ProfError::TomlError => write!(
-                f,
-                "\x1b[1;31merror: \x1b[0mError in parsing Cargo.toml to derive package \
-                 name. Make sure package name is directly under [package] tag."
-            ),
             ProfError::ReadManifestError => write!(
                 f,
                 "\x1b[1;31merror: \x1b[0mCargo.toml missing. Are you sure you're in a Rust \
                  project?"
             ),
-
-            ProfError::NoNameError => write!(
-                f,
-                "\x1b[1;31merror: \x1b[0mNo package name found in Cargo.toml. Run \
-                 cargo read-manifest to make sure everything looks okay. Otherwise please \
-                 submit bug."
-            ),
-
-            ProfError::NoTargetDirectory => write!(
-                f,
-                "\x1b[1;31merror: \x1b[0mNo target output directory found in project. \
-                 Binary must be in target/debug/ or target/release/, or specify binary \
-                 path explicitly with --bin argument."
-            ),
             ProfError::OutOfMemoryError => write!(
                 f,
                 "\x1b[1;31merror: \x1b[0mValgrind's memory management: out of memory. \
@@ -105,6 +117,102 @@ impl fmt::Display for ProfError {
                 "\x1b[1;31merror: \x1b[0mError in valgrind cli call. Make sure valgrind is \
                  installed properly."
             ),
+            ProfError::BaselineNotFound(ref name) => write!(
+                f,
+                "\x1b[1;31merror: \x1b[0mNo baseline named {} found. Save one first with \
+                 --save-baseline {}.",
+                name, name
+            ),
+            ProfError::InvalidBaseline(ref name) => write!(
+                f,
+                "\x1b[1;31merror: \x1b[0mBaseline {} can't be compared with this run. Make \
+                 sure it was saved with the same profiler.",
+                name
+            ),
+            ProfError::InvalidBudget(ref rule) => write!(
+                f,
+                "\x1b[1;31merror: \x1b[0mInvalid budget rule {}. Rules look like \
+                 total.Ir <= +2% or fn(my_crate::hot) <= 1000000. Check README for details.",
+                rule
+            ),
+            ProfError::BudgetExceeded(count) => write!(
+                f,
+                "\x1b[1;31merror: \x1b[0m{} budget rule(s) exceeded or matched no function.",
+                count
+            ),
+            ProfError::UnknownPackage(ref name) => write!(
+                f,
+                "\x1b[1;31merror: \x1b[0mNo package named {} in this workspace.",
+                name
+            ),
+            ProfError::UnknownTarget(ref kind, ref name) => write!(
+                f,
+                "\x1b[1;31merror: \x1b[0mNo {} target named {} found. Use -p to pick the \
+                 package it belongs to.",
+                kind, name
+            ),
+            ProfError::AmbiguousTarget(ref candidates) if candidates.is_empty() => write!(
+                f,
+                "\x1b[1;31merror: \x1b[0mNo binary to profile found. Use -p, --bin or \
+                 --example to pick one."
+            ),
+            ProfError::AmbiguousTarget(ref candidates) => write!(
+                f,
+                "\x1b[1;31merror: \x1b[0mCould not determine which binary to profile. Use -p, \
+                 --bin or --example to pick one of: {}",
+                candidates.join(", ")
+            ),
+            ProfError::InvalidCacheConfig(ref name) => write!(
+                f,
+                "\x1b[1;31merror: \x1b[0mUnknown cache config {}. available presets are {}.",
+                name,
+                crate::cache::CACHE_PRESETS
+                    .iter()
+                    .map(|x| x.0)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ProfError::InvalidLatencies(ref latencies) => write!(
+                f,
+                "\x1b[1;31merror: \x1b[0mInvalid latencies {}. Give the cycles of an L1 hit, \
+                 an LL hit and a RAM access, e.g. 1,5,35.",
+                latencies
+            ),
+            ProfError::ValgrindErrors(count) => write!(
+                f,
+                "\x1b[1;31merror: \x1b[0mvalgrind found {} error(s).",
+                count
+            ),
+            ProfError::InvalidGroupBy(ref group) => write!(
+                f,
+                "\x1b[1;31merror: \x1b[0mInvalid grouping {}. Group by crate, module, file \
+                 or function.",
+                group
+            ),
+            ProfError::ValgrindFailed(ref status, ref stderr) if stderr.is_empty() => {
+                write!(
+                    f,
+                    "\x1b[1;31merror: \x1b[0mvalgrind failed with {}.",
+                    status
+                )
+            }
+            ProfError::ValgrindFailed(ref status, ref stderr) => write!(
+                f,
+                "\x1b[1;31merror: \x1b[0mvalgrind failed with {}:\n{}",
+                status, stderr
+            ),
+            ProfError::NoCallGraph(ref name) => write!(
+                f,
+                "\x1b[1;31merror: \x1b[0m{} records no call graph, --focus and --flamegraph \
+                 need callgrind.",
+                name
+            ),
+            ProfError::CargoError(ref err) => write!(
+                f,
+                "\x1b[1;31merror: \x1b[0mcould not run cargo: {}. Make sure cargo is in \
+                 your PATH.",
+                err
+            ),
         }
     }
 }
@@ -113,42 +221,68 @@ impl error::Error for ProfError {
     fn description(&self) -> &str {
         match *self {
             ProfError::RegexError => "Regex error. file bug.",
-            ProfError::InvalidProfiler => "Invalid Profiler.",
+            ProfError::InvalidProfiler(_) => "Invalid Profiler.",
             ProfError::InvalidBinary => "Invalid Binary.",
             ProfError::InvalidNum => "Invalid number.",
-            ProfError::InvalidSortMetric => "Invalid sort metric.",
+            ProfError::InvalidSortMetric(_, _) => "Invalid sort metric.",
+            ProfError::InvalidOutputFormat => "Invalid output format.",
             ProfError::MisalignedData => "Misaligned Data. File bug.",
+            ProfError::MalformedOutput(_) => "Malformed profiler output. File bug.",
             ProfError::CompilationError(_, _) => {
                 "Failed to compile. Run cargo build to get compilation error."
             }
-            ProfError::TomlError => "Error in parsing Cargo.toml.",
             ProfError::ReadManifestError => "Error in reading the manifest of this crate.",
-            ProfError::NoNameError => "No package name found in Cargo.toml",
-            ProfError::NoTargetDirectory => "No target output directory found in project.",
-            ProfError::IOError(ref err) => err.description(),
+            ProfError::IOError(_) => "IO error.",
             ProfError::OutOfMemoryError => "out of memory.",
             ProfError::CliError => "make sure valgrind is installed properly.",
             ProfError::UTF8Error => "utf8 error. file bug.",
+            ProfError::BaselineNotFound(_) => "Baseline not found.",
+            ProfError::InvalidBaseline(_) => "Baseline can't be compared.",
+            ProfError::InvalidBudget(_) => "Invalid budget rule.",
+            ProfError::BudgetExceeded(_) => "Budget exceeded.",
+            ProfError::UnknownPackage(_) => "Unknown package.",
+            ProfError::UnknownTarget(_, _) => "Unknown target.",
+            ProfError::AmbiguousTarget(_) => "Ambiguous target.",
+            ProfError::InvalidCacheConfig(_) => "Invalid cache config.",
+            ProfError::InvalidLatencies(_) => "Invalid latencies.",
+            ProfError::ValgrindErrors(_) => "Valgrind found errors.",
+            ProfError::InvalidGroupBy(_) => "Invalid grouping.",
+            ProfError::ValgrindFailed(_, _) => "Valgrind failed.",
+            ProfError::NoCallGraph(_) => "No call graph.",
+            ProfError::CargoError(_) => "Could not run cargo.",
         }
     }
 
     fn cause(&self) -> Option<&dyn error::Error> {
         match *self {
             ProfError::RegexError => None,
-            ProfError::InvalidProfiler => None,
+            ProfError::InvalidProfiler(_) => None,
             ProfError::InvalidBinary => None,
             ProfError::InvalidNum => None,
-            ProfError::InvalidSortMetric => None,
+            ProfError::InvalidSortMetric(_, _) => None,
+            ProfError::InvalidOutputFormat => None,
             ProfError::MisalignedData => None,
-            ProfError::TomlError => None,
+            ProfError::MalformedOutput(_) => None,
             ProfError::IOError(ref err) => Some(err),
             ProfError::CompilationError(_, _) => None,
             ProfError::ReadManifestError => None,
-            ProfError::NoNameError => None,
-            ProfError::NoTargetDirectory => None,
             ProfError::OutOfMemoryError => None,
             ProfError::CliError => None,
             ProfError::UTF8Error => None,
+            ProfError::BaselineNotFound(_) => None,
+            ProfError::InvalidBaseline(_) => None,
+            ProfError::InvalidBudget(_) => None,
+            ProfError::BudgetExceeded(_) => None,
+            ProfError::UnknownPackage(_) => None,
+            ProfError::UnknownTarget(_, _) => None,
+            ProfError::AmbiguousTarget(_) => None,
+            ProfError::InvalidCacheConfig(_) => None,
+            ProfError::InvalidLatencies(_) => None,
+            ProfError::ValgrindErrors(_) => None,
+            ProfError::InvalidGroupBy(_) => None,
+            ProfError::ValgrindFailed(_, _) => None,
+            ProfError::NoCallGraph(_) => None,
+            ProfError::CargoError(_) => None,
         }
     }
 }
@@ -0,0 +1,371 @@
+use crate::callgraph::CallGraph;
+use std::fmt::Write;
+
+/// Width of the image in pixels.
+const WIDTH: f64 = 1200.;
+/// Horizontal padding around the frames.
+const XPAD: f64 = 10.;
+/// Height of a single frame.
+const FRAME_HEIGHT: f64 = 16.;
+/// Space above the frames for the title and the buttons.
+const TOP: f64 = 60.;
+/// Space below the frames for the details of the hovered frame.
+const BOTTOM: f64 = 30.;
+/// Stacks are not expanded beyond this depth.
+const MAX_DEPTH: usize = 256;
+/// Frames narrower than this fraction of the total are dropped.
+const MIN_FRACTION: f64 = 0.0001;
+
+/// A frame of the flamegraph: one function on one call stack.
+pub struct Frame {
+    /// index of the function in the call graph, `None` for the synthetic root
+    pub node: Option<usize>,
+    pub width: f64,
+    pub children: Vec<Frame>,
+}
+
+/// Reconstruct call stacks from the call graph.
+///
+/// Callgrind only records caller/callee pairs, not full stacks, so a function's cost is
+/// split over its callers in proportion to the cost of each call. Stacks start at the
+/// functions nobody calls and recursion is cut off where a function is already on the
+/// stack.
+pub fn frames(graph: &CallGraph) -> Frame {
+    let called = graph
+        .edges
+        .iter()
+        .filter(|x| x.caller != x.callee)
+        .map(|x| x.callee)
+        .collect::<std::collections::HashSet<_>>();
+    let mut roots = (0..graph.nodes.len())
+        .filter(|x| !called.contains(x))
+        .collect::<Vec<_>>();
+    // everything is part of a cycle, start at the most expensive function instead
+    if roots.is_empty() {
+        roots.extend((0..graph.nodes.len()).max_by(|&a, &b| {
+            graph.nodes[a]
+                .inclusive
+                .partial_cmp(&graph.nodes[b].inclusive)
+                .unwrap()
+        }));
+    }
+
+    let total = roots.iter().map(|&x| graph.nodes[x].inclusive).sum::<f64>();
+    let mut stack = Vec::new();
+    let children = roots
+        .into_iter()
+        .filter_map(|x| expand(graph, x, graph.nodes[x].inclusive, total, &mut stack))
+        .collect();
+
+    Frame {
+        node: None,
+        width: total,
+        children,
+    }
+}
+
+fn expand(
+    graph: &CallGraph,
+    node: usize,
+    width: f64,
+    total: f64,
+    stack: &mut Vec<usize>,
+) -> Option<Frame> {
+    if width < total * MIN_FRACTION || width <= 0. {
+        return None;
+    }
+    let mut frame = Frame {
+        node: Some(node),
+        width,
+        children: Vec::new(),
+    };
+    if stack.len() >= MAX_DEPTH {
+        return Some(frame);
+    }
+
+    stack.push(node);
+    let scale = width / graph.nodes[node].inclusive;
+    for edge in graph.callees(node) {
+        if stack.contains(&edge.callee) {
+            continue;
+        }
+        if let Some(child) = expand(graph, edge.callee, edge.inclusive * scale, total, stack) {
+            frame.children.push(child);
+        }
+    }
+    stack.pop();
+
+    Some(frame)
+}
+
+/// Escape text for use in XML.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// A warm color that is stable for a function name.
+fn color(name: &str) -> String {
+    let hash = name
+        .bytes()
+        .fold(5381u32, |h, b| h.wrapping_mul(33) ^ u32::from(b));
+    let (a, b, c) = (hash % 256, (hash >> 8) % 256, (hash >> 16) % 256);
+    format!(
+        "rgb({},{},{})",
+        205 + a * 50 / 255,
+        b * 230 / 255,
+        c * 55 / 255
+    )
+}
+
+/// Shorten `name` so it fits into a frame `pixels` wide.
+fn fit(name: &str, pixels: f64) -> String {
+    let chars = ((pixels - 6.) / 7.).floor();
+    if chars < 3. {
+        String::new()
+    } else if name.chars().count() <= chars as usize {
+        name.to_string()
+    } else {
+        name.chars().take(chars as usize - 2).collect::<String>() + ".."
+    }
+}
+
+fn depth(frame: &Frame) -> usize {
+    1 + frame.children.iter().map(depth).max().unwrap_or(0)
+}
+
+/// Write the `<g>` elements of `frame` and its children.
+fn write_frames(
+    out: &mut String,
+    graph: &CallGraph,
+    frame: &Frame,
+    x: f64,
+    level: usize,
+    total: f64,
+    height: f64,
+) {
+    let name = match frame.node {
+        Some(node) => graph.nodes[node].name.as_str(),
+        None => "all",
+    };
+    let fraction = frame.width / total;
+    let pixels = fraction * (WIDTH - 2. * XPAD);
+    // too narrow to see, and so are the children
+    if pixels < 0.1 {
+        return;
+    }
+    let fill = color(name);
+    let y = height - BOTTOM - (level + 1) as f64 * FRAME_HEIGHT;
+    let _ = writeln!(
+        out,
+        "<g class=\"frame\" data-name=\"{name}\" data-x=\"{x}\" data-w=\"{w}\" \
+         data-depth=\"{level}\" data-fill=\"{fill}\">\
+         <title>{name} ({cost} instructions, {perc:.2}%)</title>\
+         <rect x=\"{rx:.1}\" y=\"{y:.1}\" width=\"{rw:.1}\" height=\"{h:.1}\" fill=\"{fill}\" \
+         rx=\"2\" ry=\"2\"/><text x=\"{tx:.1}\" y=\"{ty:.1}\">{label}</text></g>",
+        name = escape(name),
+        x = x / total,
+        w = fraction,
+        level = level,
+        fill = fill,
+        cost = frame.width.round(),
+        perc = fraction * 100.,
+        rx = XPAD + x / total * (WIDTH - 2. * XPAD),
+        y = y,
+        rw = pixels,
+        h = FRAME_HEIGHT - 1.,
+        tx = XPAD + x / total * (WIDTH - 2. * XPAD) + 3.,
+        ty = y + FRAME_HEIGHT - 5.,
+        label = escape(&fit(name, pixels)),
+    );
+
+    let mut child_x = x;
+    for child in frame.children.iter() {
+        write_frames(out, graph, child, child_x, level + 1, total, height);
+        child_x += child.width;
+    }
+}
+
+/// Render the call graph as a self-contained, interactive SVG flamegraph. Click a frame
+/// to zoom into it, and use the search button to highlight functions matching a regex.
+pub fn flamegraph(graph: &CallGraph, title: &str) -> String {
+    let root = frames(graph);
+    let height = TOP + depth(&root) as f64 * FRAME_HEIGHT + BOTTOM;
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "<?xml version=\"1.0\" standalone=\"no\"?>\n\
+         <svg version=\"1.1\" width=\"{width}\" height=\"{height}\" \
+         viewBox=\"0 0 {width} {height}\" onload=\"init(evt)\" \
+         data-width=\"{inner}\" data-xpad=\"{xpad}\" \
+         xmlns=\"http://www.w3.org/2000/svg\">\n\
+         <style type=\"text/css\">\n{style}</style>\n\
+         <script type=\"text/ecmascript\"><![CDATA[\n{script}]]></script>\n\
+         <rect x=\"0\" y=\"0\" width=\"100%\" height=\"100%\" fill=\"rgb(248,248,248)\"/>\n\
+         <text class=\"title\" x=\"{center}\" y=\"24\">{title}</text>\n\
+         <text id=\"unzoom\" x=\"{xpad}\" y=\"24\" onclick=\"unzoom()\" \
+         style=\"opacity:0\">Reset Zoom</text>\n\
+         <text id=\"search\" x=\"{search_x}\" y=\"24\" onclick=\"search()\">Search</text>\n\
+         <text id=\"matched\" x=\"{search_x}\" y=\"{details_y}\"></text>\n\
+         <text id=\"details\" x=\"{xpad}\" y=\"{details_y}\"> </text>",
+        width = WIDTH,
+        height = height,
+        inner = WIDTH - 2. * XPAD,
+        xpad = XPAD,
+        style = STYLE,
+        script = SCRIPT,
+        center = WIDTH / 2.,
+        title = escape(title),
+        search_x = WIDTH - XPAD - 100.,
+        details_y = height - 10.,
+    );
+    if root.width > 0. {
+        write_frames(&mut out, graph, &root, 0., 0, root.width, height);
+    }
+    out.push_str("</svg>\n");
+    out
+}
+
+static STYLE: &str = "text { font-family: Verdana, sans-serif; font-size: 12px; fill: black; }
+.title { font-size: 17px; text-anchor: middle; }
+#search, #unzoom { cursor: pointer; }
+#search:hover, #unzoom:hover { fill: rgb(0,0,255); }
+.frame { cursor: pointer; }
+.frame:hover rect { stroke: black; stroke-width: 0.5; }
+.frame text { pointer-events: none; }
+";
+
+static SCRIPT: &str = r#"var svg, frames, details, matched, unzoombtn, W, XPAD;
+function init(evt) {
+    svg = document.documentElement;
+    frames = Array.prototype.slice.call(document.getElementsByClassName("frame"));
+    details = document.getElementById("details");
+    matched = document.getElementById("matched");
+    unzoombtn = document.getElementById("unzoom");
+    W = +svg.getAttribute("data-width");
+    XPAD = +svg.getAttribute("data-xpad");
+    frames.forEach(function(f) {
+        var title = f.getElementsByTagName("title")[0].textContent;
+        f.onmouseover = function() { details.textContent = title; };
+        f.onmouseout = function() { details.textContent = " "; };
+        f.onclick = function() { zoom(f); };
+        place(f, num(f, "data-x") * W, num(f, "data-w") * W);
+    });
+}
+function num(f, attr) { return +f.getAttribute(attr); }
+function place(f, x, w) {
+    var rect = f.getElementsByTagName("rect")[0], text = f.getElementsByTagName("text")[0];
+    var name = f.getAttribute("data-name"), chars = Math.floor((w - 6) / 7);
+    rect.setAttribute("x", XPAD + x);
+    rect.setAttribute("width", Math.max(w, 0));
+    text.setAttribute("x", XPAD + x + 3);
+    if (chars < 3) text.textContent = "";
+    else if (name.length <= chars) text.textContent = name;
+    else text.textContent = name.substring(0, chars - 2) + "..";
+}
+function zoom(target) {
+    var x0 = num(target, "data-x"), w0 = num(target, "data-w"), d0 = num(target, "data-depth");
+    var eps = 1e-9;
+    frames.forEach(function(f) {
+        var x = num(f, "data-x"), w = num(f, "data-w"), d = num(f, "data-depth");
+        if (d < d0 && x <= x0 + eps && x + w >= x0 + w0 - eps) {
+            f.style.display = "";
+            f.style.opacity = 0.5;
+            place(f, 0, W);
+        } else if (d >= d0 && x >= x0 - eps && x + w <= x0 + w0 + eps) {
+            f.style.display = "";
+            f.style.opacity = 1;
+            place(f, (x - x0) / w0 * W, w / w0 * W);
+        } else {
+            f.style.display = "none";
+        }
+    });
+    unzoombtn.style.opacity = 1;
+}
+function unzoom() {
+    frames.forEach(function(f) {
+        f.style.display = "";
+        f.style.opacity = 1;
+        place(f, num(f, "data-x") * W, num(f, "data-w") * W);
+    });
+    unzoombtn.style.opacity = 0;
+}
+function search() {
+    var term = prompt("Search for functions (regular expressions are allowed)", "");
+    if (term === null) return;
+    var re = term === "" ? null : new RegExp(term), hits = [];
+    frames.forEach(function(f) {
+        var rect = f.getElementsByTagName("rect")[0];
+        if (re && re.test(f.getAttribute("data-name"))) {
+            rect.setAttribute("fill", "rgb(230,0,230)");
+            hits.push([num(f, "data-x"), num(f, "data-w")]);
+        } else {
+            rect.setAttribute("fill", f.getAttribute("data-fill"));
+        }
+    });
+    // nested matches must not be counted twice
+    hits.sort(function(a, b) { return a[0] - b[0] || b[1] - a[1]; });
+    var covered = 0, end = 0;
+    hits.forEach(function(h) {
+        if (h[0] + h[1] > end) {
+            covered += h[0] + h[1] - Math.max(h[0], end);
+            end = h[0] + h[1];
+        }
+    });
+    matched.textContent = re ? "Matched: " + (covered * 100).toFixed(1) + "%" : "";
+}
+"#;
+
+#[cfg(test)]
+mod test {
+    use super::{escape, fit, flamegraph, frames};
+    use crate::callgraph::CallGraph;
+
+    fn graph() -> CallGraph {
+        let mut graph = CallGraph::default();
+        let main = graph.node("main");
+        let a = graph.node("a");
+        let b = graph.node("b");
+        graph.add_self(main, 10.);
+        graph.add_self(a, 20.);
+        graph.add_self(b, 70.);
+        graph.add_call(main, a, 1, 50.);
+        graph.add_call(main, b, 1, 40.);
+        graph.add_call(a, b, 3, 30.);
+        graph.add_call(b, a, 1, 0.);
+        graph
+    }
+
+    #[test]
+    fn test_frames() {
+        let root = frames(&graph());
+        assert_eq!(root.width, 100.);
+        assert_eq!(root.children.len(), 1);
+
+        let main = &root.children[0];
+        assert_eq!(main.node, Some(0));
+        assert_eq!(main.children.len(), 2);
+        assert_eq!(main.children[0].node, Some(1));
+        assert_eq!(main.children[0].width, 50.);
+        assert_eq!(main.children[0].children[0].width, 30.);
+        assert_eq!(main.children[1].node, Some(2));
+        assert!(main.children[1].children.is_empty());
+    }
+
+    #[test]
+    fn test_flamegraph() {
+        let svg = flamegraph(&graph(), "<test>");
+        assert!(svg.starts_with("<?xml"));
+        assert!(svg.ends_with("</svg>\n"));
+        assert!(svg.contains("&lt;test&gt;"));
+        assert_eq!(svg.matches("<g class=\"frame\"").count(), 5);
+        assert!(svg.contains(">main</text>"));
+        assert_eq!(escape("a<b>&'\""), "a&lt;b&gt;&amp;&apos;&quot;");
+        assert_eq!(fit("core::ptr::drop_in_place", 100.), "core::ptr::..");
+        assert_eq!(fit("main", 20.), "");
+    }
+}
@@ -0,0 +1,39 @@
+//! Profile Rust binaries with valgrind tools such as callgrind and cachegrind.
+//!
+//! The `cargo profiler` command is a thin layer on top of this crate. Tools that want to
+//! profile binaries and work with the results themselves can use the [`Builder`]:
+//!
+//! ```no_run
+//! use cargo_profiler::backend::CacheGrind;
+//! use cargo_profiler::Builder;
+//!
+//! let backend = CacheGrind {
+//!     sort: Some("D1mr".to_string()),
+//!     ..CacheGrind::default()
+//! };
+//! let profile = Builder::new(backend).binary("target/release/foo").run()?;
+//! let report = profile.report();
+//! for (event, total) in report.totals.iter() {
+//!     println!("{}: {}", event, total);
+//! }
+//! # Ok::<(), cargo_profiler::ProfError>(())
+//! ```
+
+pub mod backend;
+pub mod baseline;
+pub mod budget;
+pub mod builder;
+pub mod cache;
+pub mod callgraph;
+pub mod cargo;
+pub mod debuginfo;
+pub mod display;
+pub mod err;
+pub mod flamegraph;
+pub mod parse;
+pub mod profiler;
+pub mod report;
+
+pub use crate::backend::{Backend, Results};
+pub use crate::builder::{Builder, Profile};
+pub use crate::err::ProfError;
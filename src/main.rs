@@ -1,20 +1,18 @@
-pub mod argparse;
-pub mod cargo;
-pub mod display;
-pub mod err;
-pub mod parse;
-pub mod profiler;
-
-use crate::argparse::{get_binary, get_num, get_profiler, get_sort_metric};
-use crate::cargo::build_binary;
-use crate::err::ProfError;
-use crate::parse::cachegrind::CacheGrindParser;
-use crate::parse::callgrind::CallGrindParser;
-use crate::profiler::Profiler;
+mod argparse;
+
+use crate::argparse::{get_builder, get_num, get_output_format, get_profiler};
+use cargo_profiler::backend::registry;
+use cargo_profiler::baseline::{self, Diff};
+use cargo_profiler::budget::{check, read_rules, Rule};
+use cargo_profiler::cache::geometry;
+use cargo_profiler::debuginfo::has_debug_info;
+use cargo_profiler::display::Focus;
+use cargo_profiler::err::ProfError;
+use cargo_profiler::flamegraph::flamegraph;
+use cargo_profiler::report::OutputFormat;
 use clap::{App, AppSettings, Arg, SubCommand};
-use std::ffi::OsStr;
+use std::fs;
 use std::process;
-use std::process::Command;
 
 // macro to try something, but print custom error message and exit upon error.
 macro_rules! try_or_exit {
@@ -22,7 +20,7 @@ macro_rules! try_or_exit {
         match $e {
             Ok(e) => e,
             Err(e) => {
-                println!("{}", e);
+                eprintln!("{}", e);
                 process::exit(1);
             }
         }
@@ -30,7 +28,7 @@ macro_rules! try_or_exit {
 }
 
 fn main() {
-    let _ = real_main();
+    try_or_exit!(real_main());
 }
 
 // #[cfg(all(unix, any(target_os = "linux", target_os = "macos")))]
@@ -38,10 +36,59 @@ fn main() {
 fn real_main() -> Result<(), ProfError> {
     // create binary path argument
     let binary_arg = Arg::with_name("binary")
-        .long("bin")
-        .value_name("BINARY")
+        .long("binary")
+        .value_name("PATH")
         .required(false)
-        .help("binary you want to profile");
+        .conflicts_with_all(&[
+            "package", "bin", "example", "test", "bench", "profile", "features", "target",
+        ])
+        .help("path of an already built binary you want to profile");
+
+    // create package argument
+    let package_arg = Arg::with_name("package")
+        .short("p")
+        .long("package")
+        .value_name("NAME")
+        .takes_value(true)
+        .help("workspace member to build");
+
+    // create cargo binary target argument
+    let bin_arg = Arg::with_name("bin")
+        .long("bin")
+        .value_name("NAME")
+        .takes_value(true)
+        .conflicts_with_all(&["example", "test", "bench"])
+        .help("binary target to build and profile");
+
+    // create example argument
+    let example_arg = Arg::with_name("example")
+        .long("example")
+        .value_name("NAME")
+        .takes_value(true)
+        .conflicts_with_all(&["test", "bench"])
+        .help("example to build and profile");
+
+    // create test harness argument
+    let test_arg = Arg::with_name("test")
+        .long("test")
+        .value_name("NAME")
+        .takes_value(true)
+        .conflicts_with("bench")
+        .help("integration test to build and profile, a test filter can follow '--'");
+
+    // create bench harness argument
+    let bench_arg = Arg::with_name("bench")
+        .long("bench")
+        .value_name("NAME")
+        .takes_value(true)
+        .help("benchmark to build and profile, a bench filter can follow '--'");
+
+    // create target directory argument
+    let target_dir_arg = Arg::with_name("target-dir")
+        .long("target-dir")
+        .value_name("DIR")
+        .takes_value(true)
+        .help("directory for all generated artifacts");
 
     // create binary arguments positional args (aka, everything after a '--')
     let binargs_arg = Arg::with_name("binargs")
@@ -56,6 +103,42 @@ fn real_main() -> Result<(), ProfError> {
         .required(false)
         .help("whether binary should be built in release mode");
 
+    // create cargo profile argument
+    let profile_arg = Arg::with_name("profile")
+        .long("profile")
+        .value_name("PROFILE")
+        .takes_value(true)
+        .conflicts_with("release")
+        .help("build with the given cargo profile");
+
+    // create features argument
+    let features_arg = Arg::with_name("features")
+        .long("features")
+        .value_name("FEATURES")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .help("features to activate");
+
+    // create no default features argument
+    let no_default_features_arg = Arg::with_name("no-default-features")
+        .long("no-default-features")
+        .required(false)
+        .help("do not activate the default feature");
+
+    // create target triple argument
+    let target_arg = Arg::with_name("target")
+        .long("target")
+        .value_name("TRIPLE")
+        .takes_value(true)
+        .help("build for the target triple");
+
+    // don't force debug info into optimized builds
+    let no_debug_info_arg = Arg::with_name("no-debug-info")
+        .long("no-debug-info")
+        .required(false)
+        .help("don't build optimized profiles with debug info");
+
     // create function count argument
     let fn_count_arg = Arg::with_name("n")
         .short("n")
@@ -63,12 +146,58 @@ fn real_main() -> Result<(), ProfError> {
         .takes_value(true)
         .help("number of functions you want");
 
-    // create sort metric argument
-    let sort_arg = Arg::with_name("sort")
-        .long("sort")
-        .value_name("SORT")
+    // pass arguments through to valgrind
+    let valgrind_arg = Arg::with_name("valgrind-arg")
+        .long("valgrind-arg")
+        .value_name("ARG")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .allow_hyphen_values(true)
+        .help("extra argument for valgrind, e.g. --valgrind-arg=--fair-sched=yes");
+
+    // create output format argument
+    let format_arg = Arg::with_name("output-format")
+        .long("output-format")
+        .value_name("FORMAT")
+        .takes_value(true)
+        .help("print results as text (default), json, csv or tsv");
+
+    // shorten generic arguments
+    let collapse_generics_arg = Arg::with_name("collapse-generics")
+        .long("collapse-generics")
+        .required(false)
+        .help("show generic arguments of functions as <…> in text output");
+
+    // store results as a baseline
+    let save_baseline_arg = Arg::with_name("save-baseline")
+        .long("save-baseline")
+        .value_name("NAME")
+        .takes_value(true)
+        .help("save results as baseline NAME in target/profiler/");
+
+    // compare results with a baseline
+    let baseline_arg = Arg::with_name("baseline")
+        .long("baseline")
+        .value_name("NAME")
+        .takes_value(true)
+        .help("compare results with baseline NAME");
+
+    // budget rules
+    let budget_arg = Arg::with_name("budget")
+        .long("budget")
+        .value_name("RULE")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .help("fail if a budget rule like 'total.Ir <= +2%' is exceeded");
+
+    // file with budget rules
+    let budget_file_arg = Arg::with_name("budget-file")
+        .long("budget-file")
+        .value_name("FILE")
         .takes_value(true)
-        .help("metric you want to sort by");
+        .help("read budget rules from FILE, one per line");
 
     // keep output files
     let keep_arg = Arg::with_name("keep")
@@ -76,37 +205,63 @@ fn real_main() -> Result<(), ProfError> {
         .required(false)
         .help("keep profiler output files");
 
+    // arguments every backend understands
+    let common_args = [
+        release,
+        profile_arg,
+        features_arg,
+        no_default_features_arg,
+        target_arg,
+        no_debug_info_arg,
+        binary_arg,
+        package_arg,
+        bin_arg,
+        example_arg,
+        test_arg,
+        bench_arg,
+        target_dir_arg,
+        binargs_arg,
+        fn_count_arg,
+        valgrind_arg,
+        format_arg,
+        collapse_generics_arg,
+        save_baseline_arg,
+        baseline_arg,
+        budget_arg,
+        budget_file_arg,
+        keep_arg,
+    ];
 
-    // create callgrind subcommand
-    let callgrind = SubCommand::with_name("callgrind")
-        .about("gets callgrind features")
-        .version("1.0")
-        .author("Suchin Gururangan")
-        .arg(release.clone())
-        .arg(binary_arg.clone())
-        .arg(binargs_arg.clone())
-        .arg(fn_count_arg.clone())
-        .arg(keep_arg.clone());
-
-    // create cachegrind subcommand
-    let cachegrind = SubCommand::with_name("cachegrind")
-        .about("gets cachegrind features")
-        .version("1.0")
-        .author("Suchin Gururangan")
-        .arg(release)
-        .arg(binary_arg)
-        .arg(binargs_arg.clone())
-        .arg(fn_count_arg)
-        .arg(sort_arg)
-        .arg(keep_arg);
+    // create one subcommand per backend, listing its metrics in the help
+    let backends = registry();
+    let metrics = backends
+        .iter()
+        .map(|backend| {
+            let lines = backend
+                .metrics()
+                .iter()
+                .map(|(name, about)| format!("    {:<14}{}", name, about))
+                .collect::<Vec<_>>();
+            format!("METRICS:\n{}", lines.join("\n"))
+        })
+        .collect::<Vec<_>>();
 
     // create profiler subcommand
-    let profiler = SubCommand::with_name("profiler")
+    let mut profiler = SubCommand::with_name("profiler")
         .about("gets callgrind features")
         .version("1.0")
-        .author("Suchin Gururangan")
-        .subcommand(callgrind)
-        .subcommand(cachegrind);
+        .author("Suchin Gururangan");
+    for (backend, metrics) in backends.iter().zip(metrics.iter()) {
+        profiler = profiler.subcommand(
+            SubCommand::with_name(backend.name())
+                .about(backend.about())
+                .version("1.0")
+                .author("Suchin Gururangan")
+                .args(&common_args)
+                .args(&backend.args())
+                .after_help(metrics.as_str()),
+        );
+    }
 
     // create profiler application
     let matches = App::new("cargo-profiler")
@@ -119,59 +274,156 @@ fn real_main() -> Result<(), ProfError> {
         .get_matches();
 
     // parse arguments from cli call
-    let (m, profiler) = try_or_exit!(get_profiler(&matches));
-    let binary = {
-        if m.is_present("binary") {
-            try_or_exit!(get_binary(&m)).to_string()
-        } else if m.is_present("release") {
-            try_or_exit!(build_binary(true))
-        } else {
-            try_or_exit!(build_binary(false))
-        }
-    };
+    let (m, backend) = try_or_exit!(get_profiler(&matches));
+    let builder = try_or_exit!(get_builder(m, backend));
+    let format = try_or_exit!(get_output_format(m));
+    let num = try_or_exit!(get_num(m));
+
+    let binary = try_or_exit!(builder.binary_path());
+    if has_debug_info(&binary) == Some(false) {
+        eprintln!(
+            "\x1b[1;33mwarning: \x1b[0m{} has no debug info, function names will be missing \
+             or wrong.",
+            binary
+        );
+    }
 
     let binary_name = binary.split('/').collect::<Vec<&str>>().pop().unwrap_or("");
-    let binargs: Vec<&OsStr> = match m.values_of_os("binargs") {
-        None => vec![],
-        Some(raw) => raw.collect(),
-    };
+    eprintln!(
+        "\n\x1b[1;33mProfiling \x1b[1;0m{} \x1b[0mwith {}\x1b[0m...",
+        binary_name,
+        builder.backend().name()
+    );
 
-    let num = try_or_exit!(get_num(&m));
-    let sort_metric = try_or_exit!(get_sort_metric(&m));
-
-    match profiler {
-        Profiler::CallGrind { .. } => println!(
-            "\n\x1b[1;33mProfiling \x1b[1;0m{} \x1b[0mwith callgrind\x1b[0m...",
-            binary_name
-        ),
-        Profiler::CacheGrind { .. } => println!(
-            "\n\x1b[1;33mProfiling \x1b[1;0m{} \x1b[0mwith cachegrind\x1b[0m...",
-            binary_name
-        ),
-    };
+    // run the profiler and parse its output
+    let profile = try_or_exit!(builder.run_binary(&binary));
 
-    // get the profiler output
-    let output = match profiler {
-        Profiler::CallGrind { .. } => profiler.callgrind_cli(&binary, &binargs)?,
-        Profiler::CacheGrind { .. } => profiler.cachegrind_cli(&binary, &binargs)?,
-    };
+    let geometry = geometry(&profile.output);
+    if format == OutputFormat::Text && !geometry.is_empty() {
+        let caches = geometry
+            .iter()
+            .map(|(cache, x)| format!("{} {}", cache, x))
+            .collect::<Vec<_>>();
+        println!("\n\x1b[1;33mSimulated caches \x1b[0m{}", caches.join("; "));
+    }
 
-    // parse the output into struct
-    let parsed = match profiler {
-        Profiler::CallGrind { .. } => try_or_exit!(profiler.callgrind_parse(&output, num)),
-        Profiler::CacheGrind { .. } => {
-            try_or_exit!(profiler.cachegrind_parse(&output, num, sort_metric))
+    match format {
+        // pretty-print
+        OutputFormat::Text if m.is_present("collapse-generics") => {
+            println!("{:#}", profile.results)
         }
-    };
+        OutputFormat::Text => println!("{}", profile.results),
+        OutputFormat::Json => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&profile.report())
+                    .expect("could not serialize report")
+            );
+        }
+        OutputFormat::Csv | OutputFormat::Tsv => {
+            let sep = if format == OutputFormat::Csv {
+                ','
+            } else {
+                '\t'
+            };
+            print!("{}", profile.report().to_delimited(sep));
+        }
+    }
 
-    // pretty-print
-    println!("{}", parsed);
+    if m.is_present("focus") || m.is_present("flamegraph") {
+        let graph = try_or_exit!(profile
+            .results
+            .graph()
+            .ok_or_else(|| ProfError::NoCallGraph(builder.backend().name().to_string())));
+        if let Some(pattern) = m.value_of("focus") {
+            if format == OutputFormat::Text {
+                let focus = Focus { graph, pattern };
+                if m.is_present("collapse-generics") {
+                    println!("{:#}", focus);
+                } else {
+                    println!("{}", focus);
+                }
+            }
+        }
+
+        if let Some(path) = m.value_of("flamegraph") {
+            let title = format!("Flame Graph: {}", binary_name);
+            try_or_exit!(fs::write(path, flamegraph(graph, &title)).map_err(ProfError::from));
+            eprintln!("\x1b[1;33mWrote flamegraph to \x1b[1;0m{}\x1b[0m", path);
+        }
+    }
+
+    // budget rules from the command line and the budget file
+    let mut rules = Vec::new();
+    if let Some(values) = m.values_of("budget") {
+        for rule in values {
+            rules.push(try_or_exit!(Rule::parse(rule)));
+        }
+    }
+    if let Some(path) = m.value_of("budget-file") {
+        rules.extend(try_or_exit!(read_rules(path)));
+    }
 
-    if !m.is_present("keep") {
-        // remove files generated while profiling
-        Command::new("rm").arg("cachegrind.out").output()?;
+    // baselines and budgets look at all functions, not only the first num
+    let mut exceeded = 0;
+    if m.is_present("baseline") || m.is_present("save-baseline") || !rules.is_empty() {
+        let all = profile.full_report();
+        let metric = profile.metric();
+        let target_dir = builder.target_directory();
 
-        Command::new("rm").arg("callgrind.out").output()?;
+        let old = m
+            .value_of("baseline")
+            .map(|name| try_or_exit!(baseline::load(name, &target_dir)));
+        if let (Some(name), Some(ref old)) = (m.value_of("baseline"), &old) {
+            if old.metadata.cache != all.metadata.cache {
+                eprintln!(
+                    "\x1b[1;33mwarning: \x1b[0mbaseline {} was profiled with different caches.",
+                    name
+                );
+            }
+            let mut diff = try_or_exit!(Diff::new(name, old, &all, metric));
+            diff.truncate(num);
+            // keep machine-readable output clean
+            if format == OutputFormat::Text {
+                println!("{}", diff);
+            } else {
+                eprintln!("{}", diff);
+            }
+        }
+
+        if !rules.is_empty() {
+            let outcomes = try_or_exit!(check(&rules, &all, old.as_ref(), metric));
+            let report = outcomes
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join("\n");
+            let report = format!("\n\x1b[1;33mBudgets\x1b[0m\n{}", report);
+            // keep machine-readable output clean
+            if format == OutputFormat::Text {
+                println!("{}", report);
+            } else {
+                eprintln!("{}", report);
+            }
+            exceeded = outcomes.iter().filter(|x| !x.passed).count();
+        }
+
+        if let Some(name) = m.value_of("save-baseline") {
+            let path = try_or_exit!(baseline::save(&all, name, &target_dir));
+            eprintln!(
+                "\x1b[1;33mSaved baseline \x1b[1;0m{} \x1b[0mto {}",
+                name,
+                path.display()
+            );
+        }
+    }
+
+    if exceeded > 0 {
+        try_or_exit!(Err(ProfError::BudgetExceeded(exceeded)));
+    }
+    // memory and thread errors fail the run like a test would
+    if profile.all.failures() > 0 {
+        try_or_exit!(Err(ProfError::ValgrindErrors(profile.all.failures())));
     }
 
     Ok(())
@@ -1,25 +1,9 @@
 use crate::err::ProfError;
-use crate::profiler::Profiler;
-use lazy_static::lazy_static;
-use ndarray::{stack, Array, Array2, ArrayView1, Axis};
-use regex::Regex;
+use crate::profiler::CacheGrindResults;
+use crate::report::GroupBy;
+use ndarray::{Array, Array2, ArrayView1, Axis};
 use std::cmp::Ordering::Less;
-use std::ffi::OsStr;
-use std::process::Command;
-
-/// define cachegrind metrics
-pub enum Metric {
-    Ir,
-    I1mr,
-    ILmr,
-    Dr,
-    D1mr,
-    DLmr,
-    Dw,
-    D1mw,
-    DLmw,
-    NAN,
-}
+use std::collections::HashMap;
 
 /// Utility function for sorting a matrix. used to sort cachegrind data by particular metric (descending)
 pub fn sort_matrix(mat: &Array2<f64>, sort_col: ArrayView1<f64>) -> (Array2<f64>, Vec<usize>) {
@@ -29,204 +13,310 @@ pub fn sort_matrix(mat: &Array2<f64>, sort_col: ArrayView1<f64>) -> (Array2<f64>
     (mat.select(Axis(0), indices.as_slice()), indices)
 }
 
-/// Parser trait. To parse the output of Profilers, we first have to get their output from
-/// the command line, and then parse the output into respective structs.
-pub trait CacheGrindParser {
-    fn cachegrind_cli(&self, binary: &str, binargs: &[&OsStr]) -> Result<String, ProfError>;
-    fn cachegrind_parse<'b>(
-        &'b self,
-        output: &'b str,
-        num: usize,
-        sort_metric: Metric,
-    ) -> Result<Profiler, ProfError>;
+/// Latencies in cycles used to estimate the cycle count from the cache events.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Latencies {
+    /// access that hits the L1 cache
+    pub l1: f64,
+    /// access that misses L1 and hits the last level cache
+    pub ll: f64,
+    /// access that misses all caches and goes to RAM
+    pub ram: f64,
 }
 
-impl CacheGrindParser for Profiler {
-    /// Get profiler output from stdout.
-    fn cachegrind_cli(&self, binary: &str, binargs: &[&OsStr]) -> Result<String, ProfError> {
-        // get cachegrind cli output from stdout
-        let _ = Command::new("valgrind")
-            .arg("--tool=cachegrind")
-            .arg("--cachegrind-out-file=cachegrind.out")
-            .arg(binary)
-            .args(binargs)
-            .output()
-            .or(Err(ProfError::CliError));
-
-        let cachegrind_output = Command::new("cg_annotate")
-            .arg("cachegrind.out")
-            .arg(binary)
-            .output()
-            .or(Err(ProfError::CliError));
-
-        cachegrind_output
-            .and_then(|x| String::from_utf8(x.stdout).or(Err(ProfError::UTF8Error)))
-            .or(Err(ProfError::CliError))
-    }
-    // Get parse the profiler output into respective structs.
-    fn cachegrind_parse<'b>(
-        &'b self,
-        output: &'b str,
-        num: usize,
-        sort_metric: Metric,
-    ) -> Result<Profiler, ProfError> {
-        // split output line-by-line
-        let mut out_split: Vec<&'b str> = output.split('\n').collect();
-
-        // regex identifies lines that start with digits and have characters that commonly
-        // show up in file paths
-        lazy_static! {
-            static ref CACHEGRIND_REGEX: Regex =
-                Regex::new(r"\d+\s*[a-zA-Z]*$*_*:*/+\.*@*-*|\d+\s*[a-zA-Z]*$*_*\?+:*/*\.*-*@*-*")
-                    .unwrap();
-            static ref COMPILER_TRASH: Regex = Regex::new(r"\$\w{2}\$|\$\w{3}\$").unwrap();
-            static ref ERROR_REGEX: Regex =
-                Regex::new(r"Valgrind's memory management: out of memory").unwrap();
+impl Default for Latencies {
+    fn default() -> Latencies {
+        Latencies {
+            l1: 1.,
+            ll: 5.,
+            ram: 35.,
         }
+    }
+}
 
-        let errs = out_split
-            .to_owned()
-            .into_iter()
-            .filter(|x| ERROR_REGEX.is_match(x))
-            .collect::<Vec<_>>();
+/// Name of the derived event with the estimated cycle count.
+pub const CYCLES: &str = "Cycles";
 
-        if !errs.is_empty() {
-            return Err(ProfError::OutOfMemoryError);
-        }
+/// Estimate the cycles spent on the counts of a row, weighting L1 hits, LL hits and RAM
+/// accesses with their latencies. `None` if caches were not simulated.
+pub fn estimated_cycles(events: &[String], row: &[f64], latencies: &Latencies) -> Option<f64> {
+    let count = |name: &str| events.iter().position(|x| x == name).map(|idx| row[idx]);
+    let sum = |names: &[&str]| names.iter().map(|x| count(x)).sum::<Option<f64>>();
 
-        out_split.retain(|x| CACHEGRIND_REGEX.is_match(x));
+    let accesses = sum(&["Ir", "Dr", "Dw"])?;
+    let l1_misses = sum(&["I1mr", "D1mr", "D1mw"])?;
+    let ll_misses = sum(&["ILmr", "DLmr", "DLmw"])?;
+    Some(
+        (accesses - l1_misses) * latencies.l1
+            + (l1_misses - ll_misses) * latencies.ll
+            + ll_misses * latencies.ram,
+    )
+}
 
-        let mut funcs: Vec<String> = Vec::new();
-        let mut data_vec: Vec<Array2<f64>> = Vec::new();
+/// Column of the event to sort by, matched case-insensitively. Sorting defaults to the
+/// first event, which is `Ir` in every cachegrind profile.
+pub fn sort_column(events: &[String], sort: Option<&str>) -> Result<usize, ProfError> {
+    match sort {
+        None => Ok(0),
+        Some(name) => events
+            .iter()
+            .position(|x| x.eq_ignore_ascii_case(name))
+            .ok_or_else(|| ProfError::InvalidSortMetric(name.to_string(), events.to_vec())),
+    }
+}
 
-        // loop through each line and get numbers + func
-        for sample in out_split.iter() {
-            // trim the sample, split by whitespace to separate out each data point
-            // (numbers + func)
-            let mut elems = sample.trim().split(' ').collect::<Vec<&'b str>>();
-            // remove any empty strings
-            elems.retain(|x| x != &"");
+/// Parse a raw cachegrind profile into the cachegrind struct. The file consists of a
+/// `desc:`/`cmd:`/`events:` header followed by `fl=`/`fn=` lines and cost lines of the
+/// form `line count...`, where trailing zero counts may be left out.
+pub fn parse(
+    output: &str,
+    num: usize,
+    sort: Option<&str>,
+    latencies: &Latencies,
+    group_by: GroupBy,
+) -> Result<CacheGrindResults, ProfError> {
+    // the events of the profile, in the order of the counts on every cost line
+    let mut events: Option<Vec<String>> = None;
 
-            // for each number, remove any commas and parse into f64. the last element in
-            // data_elems is the function file path.
-            let mut numbers = Vec::new();
+    let mut file = String::from("???");
+    let mut func: Option<usize> = None;
 
-            for elem in elems[0..elems.len() - 1].iter() {
-                let number = match elem.trim().replace(",", "").parse::<f64>() {
-                    Ok(n) => n,
-                    Err(_) => return Err(ProfError::RegexError),
-                };
+    let mut index: HashMap<String, usize> = HashMap::new();
+    let mut funcs: Vec<String> = Vec::new();
+    let mut rows: Vec<Vec<f64>> = Vec::new();
 
-                numbers.push(number);
-            }
+    for line in output.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
 
-            // reshape the vector of parsed numbers into a 1 x 9 matrix, and push the
-            // matrix to our vector of 1 x 9 matrices.
-            if let Ok(data_col) = Array::from_shape_vec((numbers.len(), 1), numbers) {
-                data_vec.push(data_col);
+        if let Some(name) = line.strip_prefix("fl=") {
+            file = name.to_string();
+        } else if let Some(name) = line.strip_prefix("fn=") {
+            let name = group_by.label(&file, name);
+            let idx = *index.entry(name.clone()).or_insert_with(|| {
+                funcs.push(name);
+                rows.push(Vec::new());
+                funcs.len() - 1
+            });
+            func = Some(idx);
+        } else if let Some(names) = line.strip_prefix("events:") {
+            events = Some(names.split_whitespace().map(String::from).collect());
+        } else if line.starts_with(|c: char| c.is_ascii_digit()) {
+            let events = events.as_ref().ok_or_else(|| {
+                ProfError::MalformedOutput("cost line before events: line".to_string())
+            })?;
+            let row = match func {
+                Some(idx) => &mut rows[idx],
+                None => continue,
+            };
+            row.resize(events.len(), 0.);
+            // the first number is the line number
+            for (count, total) in line.split_whitespace().skip(1).zip(row.iter_mut()) {
+                *total += count
+                    .parse::<f64>()
+                    .map_err(|_| ProfError::MalformedOutput(format!("bad count {}", count)))?;
             }
-            // the last element in data_elems is the function file path.
-            // get the file in the file-path (which includes the function) and push that to
-            // the funcs vector.
-            let path = elems[elems.len() - 1].split('/').collect::<Vec<&'b str>>();
-            let func = path[path.len() - 1];
-
-            let mut func = COMPILER_TRASH.replace_all(func, "");
-            let idx = func.rfind("::").unwrap_or_else(|| func.len());
-            func.to_mut().drain(idx..).collect::<String>();
-            funcs.push(func.into_owned());
         }
+    }
 
-        // stack all the 1 x 9 matrices in data to a n x 9  matrix.
-        let data_matrix = match stack(
-            Axis(1),
-            &data_vec
-                .iter()
-                .map(|x| x.view())
-                .collect::<Vec<_>>()
-                .as_slice(),
-        ) {
-            Ok(m) => m.t().to_owned(),
-            Err(_) => return Err(ProfError::MisalignedData),
-        };
+    let mut events = match events {
+        Some(events) if !events.is_empty() => events,
+        _ => {
+            return Err(ProfError::MalformedOutput(
+                "no events: line found".to_string(),
+            ))
+        }
+    };
 
-        // match the sort argument to a column of the matrix that we will sort on.
-        // default sorting -> first column (total instructions).
-        let sort_col = match sort_metric {
-            Metric::Ir => data_matrix.column(0),
-            Metric::I1mr => data_matrix.column(1),
-            Metric::ILmr => data_matrix.column(2),
-            Metric::Dr => data_matrix.column(3),
-            Metric::D1mr => data_matrix.column(4),
-            Metric::DLmr => data_matrix.column(5),
-            Metric::Dw => data_matrix.column(6),
-            Metric::D1mw => data_matrix.column(7),
-            Metric::DLmw => data_matrix.column(8),
-            Metric::NAN => data_matrix.column(0),
-        };
+    // build the functions x events matrix of counts, one row per function.
+    for row in rows.iter_mut() {
+        row.resize(events.len(), 0.);
+    }
+    // with simulated caches, estimate the cycles as an additional event
+    if estimated_cycles(&events, &vec![0.; events.len()], latencies).is_some() {
+        for row in rows.iter_mut() {
+            let cycles = estimated_cycles(&events, row, latencies).unwrap_or(0.);
+            row.push(cycles);
+        }
+        events.push(CYCLES.to_string());
+    }
+    let data_matrix = Array::from_shape_vec(
+        (rows.len(), events.len()),
+        rows.into_iter().flatten().collect(),
+    )
+    .or(Err(ProfError::MisalignedData))?;
 
-        // sort the matrix of data and functions by a particular column.
-        // to sort matrix, we keep track of sorted indices, and select the matrix wrt
-        // these sorted indices. to sort functions, we index the funcs vector with the
-        // sorted indices.
-        let (mut sorted_data_matrix, indices) = sort_matrix(&data_matrix, sort_col);
+    // sort the matrix of data and functions by the column of the sort event.
+    // to sort matrix, we keep track of sorted indices, and select the matrix wrt
+    // these sorted indices. to sort functions, we index the funcs vector with the
+    // sorted indices.
+    let sort_idx = sort_column(&events, sort)?;
+    let sort_col = data_matrix.column(sort_idx);
+    let (mut sorted_data_matrix, indices) = sort_matrix(&data_matrix, sort_col);
 
-        let mut sorted_funcs: Vec<String> = indices
-            .iter()
-            .map(|&x| (&funcs[x]).to_owned())
-            .collect::<Vec<String>>();
-
-        // sum the columns of the data matrix to get total metrics.
-        let ir = sorted_data_matrix.column(0).scalar_sum();
-        let i1mr = sorted_data_matrix.column(1).scalar_sum();
-        let ilmr = sorted_data_matrix.column(2).scalar_sum();
-        let dr = sorted_data_matrix.column(3).scalar_sum();
-        let d1mr = sorted_data_matrix.column(4).scalar_sum();
-        let dlmr = sorted_data_matrix.column(5).scalar_sum();
-        let dw = sorted_data_matrix.column(6).scalar_sum();
-        let d1mw = sorted_data_matrix.column(7).scalar_sum();
-        let dlmw = sorted_data_matrix.column(8).scalar_sum();
-
-        // parse the limit argument n, and take the first n values of data matrix/funcs
-        // vector accordingly.
-        if num < sorted_data_matrix.nrows() {
-            let ls = (0..num).collect::<Vec<_>>();
-            sorted_data_matrix = sorted_data_matrix.select(Axis(0), ls.as_slice());
-            sorted_funcs = sorted_funcs.iter().take(num).cloned().collect();
-        }
+    let mut sorted_funcs: Vec<String> = indices
+        .iter()
+        .map(|&x| funcs[x].to_owned())
+        .collect::<Vec<String>>();
+
+    // sum the columns of the data matrix to get total metrics.
+    let totals = sorted_data_matrix.sum_axis(Axis(0)).to_vec();
 
-        // put all data in cachegrind struct!
-        Ok(Profiler::CacheGrind {
-            ir,
-            i1mr,
-            ilmr,
-            dr,
-            d1mr,
-            dlmr,
-            dw,
-            d1mw,
-            dlmw,
-            data: sorted_data_matrix,
-            functs: sorted_funcs,
-        })
+    // parse the limit argument n, and take the first n values of data matrix/funcs
+    // vector accordingly.
+    if num < sorted_data_matrix.nrows() {
+        let ls = (0..num).collect::<Vec<_>>();
+        sorted_data_matrix = sorted_data_matrix.select(Axis(0), ls.as_slice());
+        sorted_funcs.truncate(num);
     }
+
+    // put all data in cachegrind struct!
+    Ok(CacheGrindResults {
+        metric: events[sort_idx].clone(),
+        events,
+        totals,
+        data: sorted_data_matrix,
+        functs: sorted_funcs,
+    })
 }
 
 #[cfg(test)]
 mod test {
+    use super::{estimated_cycles, parse, Latencies};
+    use crate::profiler::CacheGrindResults;
+    use crate::report::GroupBy;
+
+    const OUTPUT: &str = "desc: I1 cache:         32768 B, 64 B, 8-way associative\n\
+                          desc: D1 cache:         32768 B, 64 B, 8-way associative\n\
+                          desc: LL cache:         8388608 B, 64 B, 16-way associative\n\
+                          cmd: ./target/debug/foo\n\
+                          events: Ir I1mr ILmr Dr D1mr DLmr Dw D1mw DLmw\n\
+                          fl=/src/main.rs\n\
+                          fn=foo::main::h0000000000000001\n\
+                          1 10 1 1 4 0 0 2\n\
+                          2 20 0 0 6 1 1 3 1 1\n\
+                          fn=foo::work::h0000000000000002\n\
+                          7 50 0 0 30 2 0\n\
+                          fl=/lib/memset.S\n\
+                          fn=memset\n\
+                          0 5\n\
+                          summary: 85 1 1 40 3 1 5 1 1\n";
+
     #[test]
     fn test_cachegrind_parse_1() {
-        assert_eq!(1, 1);
+        let output = "==6072==     Valgrind's memory management: out of memory:\n ==6072==     \
+                      Whatever the reason, Valgrind cannot continue.  Sorry.";
+        assert!(parse(output, 10, None, &Latencies::default(), GroupBy::Function).is_err());
     }
 
     #[test]
     fn test_cachegrind_parse_2() {
-        assert_eq!(1, 1);
-        assert_eq!(1, 1);
+        let CacheGrindResults {
+            events,
+            totals,
+            data,
+            functs,
+            metric,
+        } = parse(OUTPUT, 10, None, &Latencies::default(), GroupBy::Function).unwrap();
+        assert_eq!(metric, "Ir");
+        assert_eq!(events.len(), 10);
+        assert_eq!(events[9], "Cycles");
+        assert_eq!(
+            (totals[0], totals[3], totals[6], totals[7]),
+            (85., 40., 5., 1.)
+        );
+        assert_eq!(
+            functs,
+            vec!["main.rs:foo::work", "main.rs:foo::main", "memset.S:memset"]
+        );
+        assert_eq!(
+            data.row(1).to_vec(),
+            vec![30., 1., 1., 10., 1., 1., 5., 1., 1., 147.]
+        );
+    }
+
+    #[test]
+    fn test_cachegrind_group_by() {
+        let results = parse(OUTPUT, 10, None, &Latencies::default(), GroupBy::Crate).unwrap();
+        assert_eq!(results.functs, vec!["foo", "???"]);
+        assert_eq!(results.data.row(0)[0], 80.);
+        assert_eq!(results.totals[0], 85.);
+
+        let results = parse(OUTPUT, 10, None, &Latencies::default(), GroupBy::File).unwrap();
+        assert_eq!(results.functs, vec!["main.rs", "memset.S"]);
+    }
+
+    #[test]
+    fn test_estimated_cycles() {
+        let events = [
+            "Ir", "I1mr", "ILmr", "Dr", "D1mr", "DLmr", "Dw", "D1mw", "DLmw",
+        ]
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>();
+        // 100 accesses: 90 L1 hits, 8 LL hits and 2 RAM accesses
+        let row = [60., 2., 1., 30., 4., 1., 10., 4., 0.];
+        let latencies = Latencies {
+            l1: 1.,
+            ll: 10.,
+            ram: 100.,
+        };
+        assert_eq!(estimated_cycles(&events, &row, &latencies), Some(370.));
+        assert_eq!(estimated_cycles(&events[..1], &row, &latencies), None);
+
+        let CacheGrindResults { totals, functs, .. } =
+            parse(OUTPUT, 1, Some("cycles"), &latencies, GroupBy::Function).unwrap();
+        assert_eq!(functs, vec!["main.rs:foo::main"]);
+        // 130 accesses, 5 L1 misses and 3 LL misses
+        assert_eq!(totals[9], 125. + 2. * 10. + 3. * 100.);
+    }
+
+    #[test]
+    fn test_cachegrind_parse_branches() {
+        let output =
+            "events: Ir Bc Bcm Bi Bim\nfl=a.rs\nfn=a::x::h0000000000000001\n1 10 4 1 2 2\n\
+                      fn=a::y::h0000000000000002\n2 20 6 0\n";
+        let CacheGrindResults { totals, functs, .. } = parse(
+            output,
+            10,
+            Some("bim"),
+            &Latencies::default(),
+            GroupBy::Function,
+        )
+        .unwrap();
+        assert_eq!(totals, vec![30., 10., 1., 2., 2.]);
+        assert_eq!(functs[0], "a.rs:a::x");
     }
 
     #[test]
     fn test_cachegrind_parse_3() {
-        assert_eq!(1, 1);
+        // only instructions are counted when cache simulation is off
+        let output = "cmd: foo\nevents: Ir\nfl=a.rs\nfn=a::x::h0000000000000001\n1 3\nfn=a::y::h0000000000000002\n2 9\n";
+        let CacheGrindResults {
+            events,
+            totals,
+            functs,
+            ..
+        } = parse(
+            output,
+            1,
+            Some("IR"),
+            &Latencies::default(),
+            GroupBy::Function,
+        )
+        .unwrap();
+        assert_eq!((events, totals), (vec!["Ir".to_string()], vec![12.]));
+        assert_eq!(functs, vec!["a.rs:a::y"]);
+
+        assert!(parse(
+            output,
+            1,
+            Some("dr"),
+            &Latencies::default(),
+            GroupBy::Function
+        )
+        .is_err());
     }
 }
@@ -1,133 +1,319 @@
+use crate::callgraph::CallGraph;
 use crate::err::ProfError;
-use crate::profiler::Profiler;
-use lazy_static::lazy_static;
-use regex::Regex;
-use std::ffi::OsStr;
-use std::process::Command;
-
-// Parser trait. To parse the output of Profilers, we first have to get their output from
-// the command line, and then parse the output into respective structs.
-pub trait CallGrindParser {
-    fn callgrind_cli(&self, binary: &str, binargs: &[&OsStr]) -> Result<String, ProfError>;
-    fn callgrind_parse<'b>(&'b self, output: &'b str, num: usize) -> Result<Profiler, ProfError>;
-}
+use crate::profiler::CallGrindResults;
+use crate::report::GroupBy;
+use std::collections::HashMap;
 
-impl CallGrindParser for Profiler {
-    // Get profiler output from stdout.
-    fn callgrind_cli(&self, binary: &str, binargs: &[&OsStr]) -> Result<String, ProfError> {
-        // get callgrind cli output from stdout
-        Command::new("valgrind")
-            .arg("--tool=callgrind")
-            .arg("--callgrind-out-file=callgrind.out")
-            .arg(binary)
-            .args(binargs)
-            .output()
-            .unwrap_or_else(|e| panic!("failed to execute process: {}", e));
-
-        let cachegrind_output = Command::new("callgrind_annotate")
-            .arg("callgrind.out")
-            .arg(binary)
-            .output()
-            .unwrap_or_else(|e| panic!("failed to execute process: {}", e));
-
-        Ok(String::from_utf8(cachegrind_output.stdout)
-            .expect("error while returning cachegrind stdout"))
-    }
+/// Resolves callgrind's name compression. The first occurrence of a name is written as
+/// `(id) name`, every later occurrence only as `(id)`.
+#[derive(Default)]
+struct NameTable {
+    names: HashMap<String, String>,
+}
 
-    fn callgrind_parse<'b>(&'b self, output: &'b str, num: usize) -> Result<Profiler, ProfError> {
-        // split output line-by-line
-        let mut out_split = output.split('\n').collect::<Vec<_>>();
-
-        // regex identifies lines that start with digits and have characters that commonly
-        // show up in file paths
-        lazy_static! {
-            static ref CALLGRIND_REGEX: Regex =
-                Regex::new(r"\d+\s*[a-zA-Z]*$*_*:*/+\.*@*-*|\d+\s*[a-zA-Z]*$*_*\?+:*/*\.*-*@*-*")
-                    .unwrap();
-            static ref COMPILER_TRASH: Regex = Regex::new(r"\$\w{2}\$|\$\w{3}\$").unwrap();
-            static ref ERROR_REGEX: Regex = Regex::new(r"out of memory").unwrap();
+impl NameTable {
+    fn resolve(&mut self, spec: &str) -> Result<String, ProfError> {
+        let spec = spec.trim();
+        if !spec.starts_with('(') {
+            return Ok(spec.to_string());
         }
-        let errs = out_split
-            .to_owned()
-            .into_iter()
-            .filter(|x| ERROR_REGEX.is_match(x))
-            .collect::<Vec<_>>();
-        if !errs.is_empty() {
-            return Err(ProfError::OutOfMemoryError);
+        let end = spec
+            .find(')')
+            .ok_or_else(|| ProfError::MalformedOutput(format!("bad compressed name {}", spec)))?;
+        let id = &spec[1..end];
+        let name = spec[end + 1..].trim();
+        if name.is_empty() {
+            self.names
+                .get(id)
+                .cloned()
+                .ok_or_else(|| ProfError::MalformedOutput(format!("unknown name id ({})", id)))
+        } else {
+            self.names.insert(id.to_string(), name.to_string());
+            Ok(name.to_string())
         }
+    }
+}
 
-        out_split.retain(|x| CALLGRIND_REGEX.is_match(x));
+/// Parse one number of a cost line. Positions may be relative (`+3`, `-2`) or the same as
+/// before (`*`); since we only aggregate per function their value does not matter.
+fn parse_cost(token: &str) -> Result<f64, ProfError> {
+    let parsed = match token.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => token.parse::<u64>().ok(),
+    };
+    parsed
+        .map(|x| x as f64)
+        .ok_or_else(|| ProfError::MalformedOutput(format!("bad cost {}", token)))
+}
 
-        let mut funcs: Vec<String> = Vec::new();
-        let mut data_vec: Vec<f64> = Vec::new();
-        // loop through each line and get numbers + func
-        for sample in out_split.iter() {
-            // trim the sample, split by whitespace to separate out each data point
-            // (numbers + func)
-            let elems = sample.trim().split("  ").collect::<Vec<_>>();
+/// Parse a raw callgrind profile (see the "Callgrind Format Specification" in the
+/// valgrind manual) into a call graph with the self and inclusive cost of every
+/// function. With `group_by`, the nodes of the graph are whole crates, modules or files
+/// and calls within one of them count like recursion.
+pub fn parse(
+    output: &str,
+    num: usize,
+    inclusive: bool,
+    group_by: GroupBy,
+) -> Result<CallGrindResults, ProfError> {
+    let mut files = NameTable::default();
+    let mut fns = NameTable::default();
+    let mut objs = NameTable::default();
 
-            // for each number, remove any commas and parse into f64. the last element in
-            // data_elems is the function file path.
+    let mut positions = 1;
+    let mut events: Option<Vec<String>> = None;
 
-            let data_row = match elems[0].trim().replace(",", "").parse::<f64>() {
-                Ok(rep) => rep,
-                Err(_) => return Err(ProfError::RegexError),
-            };
+    // current source file and function, which cost lines are attributed to. `fi=`/`fe=`
+    // switch the file for inlined code, and callees and functions named after it are in
+    // that file unless told otherwise.
+    let mut file = String::from("???");
+    let mut func: Option<usize> = None;
+    // file and function of the callee named by `cfi=`/`cfn=`
+    let mut call_file: Option<String> = None;
+    let mut callee = String::new();
+    // the cost line after `calls=` is the inclusive cost of that call, not self cost
+    let mut calls: Option<u64> = None;
+
+    let mut graph = CallGraph::default();
 
-            data_vec.push(data_row);
-
-            // the function has some trailing whitespace and trash. remove that, and
-            // get the function, push to functs vector.
-            let path = elems[1].split(' ').collect::<Vec<_>>();
-            let cleaned_path = path[0].split('/').collect::<Vec<_>>();
-            let func = cleaned_path[cleaned_path.len() - 1];
-            let mut func = COMPILER_TRASH.replace_all(func, "..");
-            let idx = func.rfind("::").unwrap_or_else(|| func.len());
-            func.to_mut().drain(idx..).collect::<String>();
-            funcs.push(func.into_owned())
+    for line in output.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
         }
 
-        // get the total instructions by summing the data vector.
-        let total_instructions = data_vec.iter().fold(0.0, |a, b| a + b);
+        // cost lines: positions followed by one number per event
+        if line.starts_with(|c: char| c.is_ascii_digit() || c == '+' || c == '-' || c == '*') {
+            let ir_idx = match events {
+                Some(ref events) => events.iter().position(|x| x == "Ir").unwrap_or(0),
+                None => {
+                    return Err(ProfError::MalformedOutput(
+                        "cost line before events: line".to_string(),
+                    ))
+                }
+            };
+            let cost = match line.split_whitespace().nth(positions + ir_idx) {
+                Some(x) => parse_cost(x)?,
+                None => 0.0,
+            };
+            if let Some(idx) = func {
+                match calls.take() {
+                    Some(count) => {
+                        let name = group_by.label(call_file.as_ref().unwrap_or(&file), &callee);
+                        let callee = graph.node(&name);
+                        graph.add_call(idx, callee, count, cost);
+                    }
+                    None => graph.add_self(idx, cost),
+                }
+            }
+            call_file = None;
+            calls = None;
+            continue;
+        }
 
-        // parse the limit argument n, and take the first n values of data/funcs vectors
-        // accordingly.
+        // specification lines such as `fn=(3) main`
+        if let Some(eq) = line.find('=') {
+            let (key, value) = (&line[..eq], &line[eq + 1..]);
+            if key.chars().all(|c| c.is_ascii_lowercase()) {
+                match key {
+                    "fl" | "fi" | "fe" => file = files.resolve(value)?,
+                    "cfi" | "cfl" => call_file = Some(files.resolve(value)?),
+                    "ob" | "cob" => {
+                        objs.resolve(value)?;
+                    }
+                    "fn" => {
+                        let name = group_by.label(&file, &fns.resolve(value)?);
+                        func = Some(graph.node(&name));
+                    }
+                    "cfn" => callee = fns.resolve(value)?,
+                    "calls" => {
+                        let count = value.split_whitespace().next().unwrap_or("");
+                        calls = Some(parse_cost(count)? as u64);
+                    }
+                    _ => (),
+                }
+                continue;
+            }
+        }
 
-        if num < data_vec.len() {
-            data_vec = data_vec.iter().take(num).cloned().collect();
-            funcs = funcs.iter().take(num).cloned().collect();
+        // header lines such as `events: Ir`
+        if let Some(colon) = line.find(':') {
+            let (key, value) = (&line[..colon], line[colon + 1..].trim());
+            match key {
+                "positions" => positions = value.split_whitespace().count(),
+                "events" => events = Some(value.split_whitespace().map(String::from).collect()),
+                _ => (),
+            }
         }
-        // put all data in cachegrind struct!
-        Ok(Profiler::CallGrind {
-            total_instructions,
-            instructions: data_vec,
-            functs: funcs,
-        })
     }
+
+    if events.is_none() {
+        return Err(ProfError::MalformedOutput(
+            "no events: line found".to_string(),
+        ));
+    }
+
+    // get the total instructions by summing the self cost of every function.
+    let total_instructions = graph.total();
+
+    // sort functions by their self or inclusive cost, most expensive first.
+    let nodes = &graph.nodes;
+    let sort_key = |x: usize| {
+        if inclusive {
+            nodes[x].inclusive
+        } else {
+            nodes[x].self_cost
+        }
+    };
+    let mut order = (0..nodes.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| sort_key(b).partial_cmp(&sort_key(a)).unwrap());
+    order.truncate(num);
+
+    // put all data in callgrind struct!
+    Ok(CallGrindResults {
+        total_instructions,
+        instructions: order.iter().map(|&x| nodes[x].self_cost).collect(),
+        inclusive: order.iter().map(|&x| nodes[x].inclusive).collect(),
+        functs: order.iter().map(|&x| nodes[x].name.clone()).collect(),
+        graph,
+        metric: if inclusive { "Ir_inclusive" } else { "Ir" }.to_string(),
+    })
 }
 
 #[cfg(test)]
 mod test {
-    use super::CallGrindParser;
-    use crate::profiler::Profiler;
+    use super::parse;
+    use crate::profiler::CallGrindResults;
+    use crate::report::GroupBy;
     #[test]
     fn test_callgrind_parse_1() {
         let output = "==6072==     Valgrind's memory management: out of memory:\n ==6072==     \
                       Whatever the reason, Valgrind cannot continue.  Sorry.";
         let num = 10;
-        let profiler = Profiler::new_callgrind();
-        let is_err = profiler.callgrind_parse(&output, num).is_err();
-        assert!(is_err && true)
+        let is_err = parse(output, num, false, GroupBy::Function).is_err();
+        assert!(is_err)
     }
 
     #[test]
     fn test_callgrind_parse_2() {
-        assert_eq!(1, 1);
-        assert_eq!(1, 1);
+        let output = "version: 1\ncreator: callgrind-3.15.0\npositions: line\nevents: Ir\n\
+                      summary: 175\n\nfl=(1) /src/main.rs\nfn=(1) foo::main::h0000000000000001\n2 5\n\
+                      cfn=(2) foo::work::h0000000000000002\ncalls=3 10\n3 150\n4 10\n\nfn=(2)\n11 150\n\n\
+                      fl=(2) /lib/memset.S\nfn=(3) memset\n0 10\n";
+        let CallGrindResults {
+            total_instructions,
+            instructions,
+            inclusive,
+            functs,
+            ..
+        } = parse(output, 10, false, GroupBy::Function).unwrap();
+        assert_eq!(total_instructions, 175.);
+        assert_eq!(instructions, vec![150., 15., 10.]);
+        assert_eq!(inclusive, vec![150., 165., 10.]);
+        assert_eq!(
+            functs,
+            vec!["main.rs:foo::work", "main.rs:foo::main", "memset.S:memset"]
+        );
     }
 
     #[test]
     fn test_callgrind_parse_3() {
-        assert_eq!(1, 1);
+        let output = "positions: instr line\nevents: Ir Dr\nfl=a.rs\nfn=a::x::h0000000000000001\n\
+                      0x10 3 4 2\n+2 * 6\n-1 +1 1 7\nfn=a::y::h0000000000000002\n0x20 9 8\n";
+        let CallGrindResults {
+            total_instructions,
+            instructions,
+            functs,
+            ..
+        } = parse(output, 1, false, GroupBy::Function).unwrap();
+        assert_eq!(total_instructions, 19.);
+        assert_eq!(instructions, vec![11.]);
+        assert_eq!(functs, vec!["a.rs:a::x"]);
+    }
+
+    #[test]
+    fn test_callgrind_parse_jumps() {
+        // with --collect-jumps=yes, jumps are followed by a line with only their position
+        let output = "positions: line\nevents: Ir\nfl=a.rs\nfn=a::x::h0000000000000001\n16 3\n\
+                      jump=1 20\n17\njcnd=2 1 18\n16\n20 4\n";
+        let CallGrindResults {
+            total_instructions, ..
+        } = parse(output, 10, false, GroupBy::Function).unwrap();
+        assert_eq!(total_instructions, 7.);
+    }
+
+    #[test]
+    fn test_callgrind_parse_inclusive() {
+        let output = "events: Ir\nfl=(1) main.rs\nfn=(1) foo::main::h0000000000000001\n1 5\n\
+                      cfn=(2) foo::fib::h0000000000000002\ncalls=1 10\n2 100\n\
+                      fn=(2)\n10 60\ncfn=(2)\ncalls=8 10\n11 40\n\
+                      cfl=(2) lib.rs\ncfn=(3) bar::baz::h0000000000000003\ncalls=1 1\n12 40\n\
+                      fl=(2)\nfn=(3)\n1 40\n";
+        let CallGrindResults {
+            total_instructions,
+            instructions,
+            inclusive,
+            functs,
+            ..
+        } = parse(output, 10, true, GroupBy::Function).unwrap();
+        assert_eq!(total_instructions, 105.);
+        assert_eq!(
+            functs,
+            vec!["main.rs:foo::main", "main.rs:foo::fib", "lib.rs:bar::baz"]
+        );
+        assert_eq!(instructions, vec![5., 60., 40.]);
+        assert_eq!(inclusive, vec![105., 100., 40.]);
+    }
+
+    #[test]
+    fn test_callgrind_parse_inlined() {
+        // main calls helper from code inlined from b.rs, then switches back to a.rs
+        let output = "events: Ir\nfl=(1) a.rs\nfn=(1) a::main::h0000000000000001\n1 5\n\
+                      fi=(2) b.rs\n2 3\ncfn=(2) b::helper::h0000000000000002\ncalls=1 2\n3 10\n\
+                      fe=(1)\n4 1\nfn=(3) a::done::h0000000000000003\n5 2\n\
+                      fl=(2)\nfn=(2)\n2 10\n";
+        let CallGrindResults {
+            total_instructions,
+            instructions,
+            inclusive,
+            functs,
+            ..
+        } = parse(output, 10, true, GroupBy::Function).unwrap();
+        assert_eq!(total_instructions, 21.);
+        assert_eq!(
+            functs,
+            vec!["a.rs:a::main", "b.rs:b::helper", "a.rs:a::done"]
+        );
+        assert_eq!(instructions, vec![9., 10., 2.]);
+        assert_eq!(inclusive, vec![19., 10., 2.]);
+    }
+
+    #[test]
+    fn test_callgrind_group_by() {
+        let output = "events: Ir\nfl=(1) main.rs\nfn=(1) foo::main::h0000000000000001\n1 5\n\
+                      cfn=(2) foo::fib::h0000000000000002\ncalls=1 10\n2 100\n\
+                      fn=(2)\n10 60\ncfn=(2)\ncalls=8 10\n11 40\n\
+                      cfl=(2) lib.rs\ncfn=(3) bar::baz::h0000000000000003\ncalls=1 1\n12 40\n\
+                      fl=(2)\nfn=(3)\n1 40\n";
+        let CallGrindResults {
+            total_instructions,
+            instructions,
+            inclusive,
+            functs,
+            graph,
+            ..
+        } = parse(output, 10, true, GroupBy::Crate).unwrap();
+        assert_eq!(total_instructions, 105.);
+        assert_eq!(functs, vec!["foo", "bar"]);
+        assert_eq!(instructions, vec![65., 40.]);
+        // calls within foo are not counted twice
+        assert_eq!(inclusive, vec![105., 40.]);
+        assert_eq!(
+            (graph.callers(1)[0].caller, graph.callers(1)[0].inclusive),
+            (0, 40.)
+        );
+
+        let results = parse(output, 10, false, GroupBy::File).unwrap();
+        assert_eq!(results.functs, vec!["main.rs", "lib.rs"]);
     }
 }
@@ -0,0 +1,222 @@
+use crate::err::ProfError;
+use crate::parse::{is_user_code, site};
+use crate::profiler::{AllocationSite, DhatResults};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Names of the metrics of every allocation site, in display order. The access metrics are
+/// only there when dhat tracked accesses.
+pub const METRICS: [&str; 5] = [
+    "total_B",
+    "total_blocks",
+    "avg_lifetime",
+    "reads_per_B",
+    "writes_per_B",
+];
+
+/// The `dhat.out` file. Only the fields we use are listed, see `dh_view.js` in the valgrind
+/// sources for all of them.
+#[derive(Deserialize)]
+struct DhatFile {
+    /// time unit, `instrs` in heap mode
+    tu: String,
+    /// whether block accesses were tracked
+    #[serde(default)]
+    bkacc: bool,
+    /// program points, i.e. the distinct allocation stacks
+    pps: Vec<ProgramPoint>,
+    /// frame table, the stacks of the program points index into it
+    ftbl: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ProgramPoint {
+    /// total bytes and blocks
+    tb: f64,
+    tbk: f64,
+    /// total lifetime of all blocks
+    #[serde(default)]
+    tl: f64,
+    /// bytes live at the global heap peak
+    #[serde(default)]
+    gb: f64,
+    /// bytes read and written
+    rb: Option<f64>,
+    wb: Option<f64>,
+    /// stack of the allocation, innermost frame first
+    fs: Vec<usize>,
+}
+
+impl AllocationSite {
+    /// Value of every metric of `METRICS`, `None` where accesses were not tracked.
+    pub fn metrics(&self) -> Vec<Option<f64>> {
+        let per_byte = |x: f64| if self.bytes > 0. { x / self.bytes } else { 0. };
+        let per_block = if self.blocks > 0. {
+            self.lifetime / self.blocks
+        } else {
+            0.
+        };
+        vec![
+            Some(self.bytes),
+            Some(self.blocks),
+            Some(per_block),
+            self.reads.map(per_byte),
+            self.writes.map(per_byte),
+        ]
+    }
+}
+
+/// Label of the first frame of a stack that is not the allocator or the standard library.
+fn allocation_site(ftbl: &[String], frames: &[usize]) -> String {
+    let labels = frames
+        .iter()
+        .filter_map(|&x| ftbl.get(x))
+        .filter_map(|x| site(x))
+        .collect::<Vec<_>>();
+    labels
+        .iter()
+        .find(|x| is_user_code(x))
+        .or_else(|| labels.first())
+        .cloned()
+        .unwrap_or_else(|| "???:???".to_string())
+}
+
+/// Parse the JSON profile dhat writes in heap mode. Program points that allocate from the
+/// same site are summed.
+pub fn parse(output: &str, num: usize, sort: Option<&str>) -> Result<DhatResults, ProfError> {
+    let file: DhatFile = serde_json::from_str(output)
+        .map_err(|e| ProfError::MalformedOutput(format!("bad dhat profile: {}", e)))?;
+
+    let mut index: HashMap<String, usize> = HashMap::new();
+    let mut sites: Vec<AllocationSite> = Vec::new();
+    let mut peak_bytes = 0.;
+    for pp in file.pps.iter() {
+        let funct = allocation_site(&file.ftbl, &pp.fs);
+        let idx = *index.entry(funct.clone()).or_insert_with(|| {
+            sites.push(AllocationSite {
+                funct,
+                ..AllocationSite::default()
+            });
+            sites.len() - 1
+        });
+        let site = &mut sites[idx];
+        site.bytes += pp.tb;
+        site.blocks += pp.tbk;
+        site.lifetime += pp.tl;
+        if file.bkacc {
+            site.reads = Some(site.reads.unwrap_or(0.) + pp.rb.unwrap_or(0.));
+            site.writes = Some(site.writes.unwrap_or(0.) + pp.wb.unwrap_or(0.));
+        }
+        peak_bytes += pp.gb;
+    }
+
+    let events = if file.bkacc {
+        &METRICS[..]
+    } else {
+        &METRICS[..3]
+    };
+    let column = match sort {
+        None => 0,
+        Some(name) => events
+            .iter()
+            .position(|x| x.eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                ProfError::InvalidSortMetric(
+                    name.to_string(),
+                    events.iter().map(|x| x.to_string()).collect(),
+                )
+            })?,
+    };
+
+    let total_bytes = sites.iter().map(|x| x.bytes).sum();
+    let total_blocks = sites.iter().map(|x| x.blocks).sum();
+    let key = |x: &AllocationSite| x.metrics()[column].unwrap_or(0.);
+    sites.sort_by(|a, b| {
+        key(b)
+            .partial_cmp(&key(a))
+            .unwrap()
+            .then_with(|| a.funct.cmp(&b.funct))
+    });
+    sites.truncate(num);
+
+    Ok(DhatResults {
+        time_unit: file.tu,
+        total_bytes,
+        total_blocks,
+        peak_bytes,
+        sites,
+        metric: events[column].to_string(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse;
+
+    const OUTPUT: &str = r#"{
+        "dhatFileVersion": 2, "mode": "heap", "verb": "Allocated",
+        "bklt": true, "bkacc": true, "tu": "instrs", "Mtu": "instr", "tuth": 500,
+        "cmd": "./foo", "pid": 42, "te": 10000, "tg": 5000,
+        "pps": [
+            {"tb": 1000, "tbk": 100, "tl": 500, "mb": 10, "mbk": 1, "gb": 10, "gbk": 1,
+             "eb": 0, "ebk": 0, "rb": 1000, "wb": 2000, "fs": [1, 2, 3, 5]},
+            {"tb": 64, "tbk": 1, "tl": 9000, "mb": 64, "mbk": 1, "gb": 64, "gbk": 1,
+             "eb": 64, "ebk": 1, "rb": 0, "wb": 64, "fs": [1, 4]},
+            {"tb": 500, "tbk": 50, "tl": 100, "mb": 10, "mbk": 1, "gb": 0, "gbk": 0,
+             "eb": 0, "ebk": 0, "rb": 0, "wb": 500, "fs": [1, 2, 5]},
+            {"tb": 8, "tbk": 1, "tl": 10, "mb": 8, "mbk": 1, "gb": 0, "gbk": 0,
+             "eb": 0, "ebk": 0, "rb": 8, "wb": 8, "fs": [1]}
+        ],
+        "ftbl": [
+            "[root]",
+            "0x4C2DB8F: malloc (in /usr/lib/valgrind/vgpreload_dhat-amd64-linux.so)",
+            "0x10A000: alloc::raw_vec::RawVec<T,A>::allocate_in::h0123456789abcdef (raw_vec.rs:186)",
+            "0x10A100: <alloc::string::String as core::clone::Clone>::clone (string.rs:1900)",
+            "0x10A200: foo::cache::Cache::new::hfedcba9876543210 (cache.rs:12)",
+            "0x10A300: bar::parse::h0011223344556677 (lib.rs:40:9)"
+        ]
+    }"#;
+
+    #[test]
+    fn test_dhat_parse() {
+        let results = parse(OUTPUT, 10, None).unwrap();
+        assert_eq!(results.time_unit, "instrs");
+        assert_eq!((results.total_bytes, results.total_blocks), (1572., 152.));
+        assert_eq!(results.peak_bytes, 74.);
+        assert_eq!(results.metric, "total_B");
+
+        let functs = results
+            .sites
+            .iter()
+            .map(|x| &x.funct[..])
+            .collect::<Vec<_>>();
+        // the String clone is attributed to its caller, not to alloc
+        assert_eq!(
+            functs,
+            vec![
+                "lib.rs:bar::parse",
+                "cache.rs:foo::cache::Cache::new",
+                "vgpreload_dhat-amd64-linux.so:malloc"
+            ]
+        );
+        assert_eq!(
+            results.sites[0].metrics(),
+            vec![
+                Some(1500.),
+                Some(150.),
+                Some(4.),
+                Some(1000. / 1500.),
+                Some(2500. / 1500.)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dhat_sort() {
+        let results = parse(OUTPUT, 1, Some("avg_lifetime")).unwrap();
+        assert_eq!(results.sites.len(), 1);
+        assert_eq!(results.sites[0].funct, "cache.rs:foo::cache::Cache::new");
+        assert!(parse(OUTPUT, 1, Some("Ir")).is_err());
+        assert!(parse("{}", 1, None).is_err());
+    }
+}
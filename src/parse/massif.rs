@@ -0,0 +1,224 @@
+use crate::err::ProfError;
+use crate::parse::{is_user_code, site};
+use crate::profiler::{MassifResults, Snapshot};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::HashMap;
+
+/// A node of a heap tree: the bytes allocated through a stack frame, the label of the frame
+/// and the indices of the frames that called it.
+struct Node {
+    bytes: f64,
+    label: Option<String>,
+    children: Vec<usize>,
+}
+
+/// Build a heap tree from its `(bytes, label, depth)` lines. The root comes first.
+fn tree(lines: Vec<(f64, Option<String>, usize)>) -> Vec<Node> {
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut path: Vec<usize> = Vec::new();
+    for (bytes, label, depth) in lines {
+        let idx = nodes.len();
+        path.truncate(depth);
+        if let Some(&parent) = path.last() {
+            nodes[parent].children.push(idx);
+        }
+        path.push(idx);
+        nodes.push(Node {
+            bytes,
+            label,
+            children: Vec::new(),
+        });
+    }
+    nodes
+}
+
+/// Attribute the bytes allocated through a node to the first frame outside the standard
+/// library on each path, like dhat does. Bytes with only the standard library on their
+/// stack stay with `fallback`, the allocation function.
+fn attribute(nodes: &[Node], idx: usize, fallback: Option<&str>, bytes: &mut HashMap<String, f64>) {
+    let node = &nodes[idx];
+    if let Some(label) = node.label.as_ref().filter(|x| is_user_code(x)) {
+        *bytes.entry(label.clone()).or_insert(0.) += node.bytes;
+        return;
+    }
+
+    let fallback = fallback.or(node.label.as_deref());
+    let mut rest = node.bytes;
+    for &child in node.children.iter() {
+        rest -= nodes[child].bytes;
+        attribute(nodes, child, fallback, bytes);
+    }
+    if let Some(label) = fallback.filter(|_| rest > 0.) {
+        *bytes.entry(label.to_string()).or_insert(0.) += rest;
+    }
+}
+
+fn number(value: &str) -> Result<f64, ProfError> {
+    value
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| ProfError::MalformedOutput(format!("bad number {}", value)))
+}
+
+/// Parse a raw massif profile. The file has a `desc:`/`cmd:`/`time_unit:` header followed
+/// by snapshots of `key=value` lines. Detailed snapshots end with a heap tree of lines like
+/// `n2: 1000 description`, indented by one space per level, where every node lists the
+/// bytes allocated through it and the number of its children.
+pub fn parse(output: &str, num: usize) -> Result<MassifResults, ProfError> {
+    lazy_static! {
+        static ref NODE: Regex = Regex::new(r"^( *)n(\d+): (\d+) (.*)$").unwrap();
+    }
+
+    let mut time_unit = String::from("i");
+    let mut snapshots: Vec<Snapshot> = Vec::new();
+    let mut peak: Option<usize> = None;
+    // heap tree of every detailed snapshot, as (bytes, label, depth) in file order
+    let mut trees: HashMap<usize, Vec<(f64, Option<String>, usize)>> = HashMap::new();
+
+    for line in output.lines() {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(caps) = NODE.captures(line) {
+            let idx = snapshots.len().checked_sub(1).ok_or_else(|| {
+                ProfError::MalformedOutput("heap tree before snapshot".to_string())
+            })?;
+            trees
+                .entry(idx)
+                .or_default()
+                .push((number(&caps[3])?, site(&caps[4]), caps[1].len()));
+        } else if let Some(unit) = line.strip_prefix("time_unit:") {
+            time_unit = unit.trim().to_string();
+        } else if line.starts_with("snapshot=") {
+            snapshots.push(Snapshot::default());
+        } else if let Some((key, value)) = line.split_once('=') {
+            let snapshot = match snapshots.last_mut() {
+                Some(snapshot) => snapshot,
+                None => continue,
+            };
+            match key {
+                "time" => snapshot.time = number(value)?,
+                "mem_heap_B" => snapshot.heap = number(value)?,
+                "mem_heap_extra_B" => snapshot.extra = number(value)?,
+                "mem_stacks_B" => snapshot.stacks = number(value)?,
+                "heap_tree" => {
+                    snapshot.detailed = value != "empty";
+                    if value == "peak" {
+                        peak = Some(snapshots.len() - 1);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if snapshots.is_empty() {
+        return Err(ProfError::MalformedOutput("no snapshots found".to_string()));
+    }
+
+    // massif marks the peak when it is detailed, otherwise take the largest snapshot
+    let peak = peak.unwrap_or_else(|| {
+        (0..snapshots.len())
+            .rev()
+            .max_by(|&a, &b| snapshots[a].heap.partial_cmp(&snapshots[b].heap).unwrap())
+            .unwrap_or(0)
+    });
+
+    // bytes allocated by every function at the peak
+    let mut bytes: HashMap<String, f64> = HashMap::new();
+    let nodes = tree(trees.remove(&peak).unwrap_or_default());
+    if !nodes.is_empty() {
+        attribute(&nodes, 0, None, &mut bytes);
+    }
+
+    let mut sites = bytes.into_iter().collect::<Vec<_>>();
+    sites.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then_with(|| a.0.cmp(&b.0)));
+    sites.truncate(num);
+
+    Ok(MassifResults {
+        time_unit,
+        snapshots,
+        peak,
+        functs: sites.iter().map(|x| x.0.clone()).collect(),
+        bytes: sites.iter().map(|x| x.1).collect(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse;
+
+    const OUTPUT: &str = "desc: --massif-out-file=massif.out\n\
+                          cmd: ./target/debug/foo\n\
+                          time_unit: i\n\
+                          #-----------\n\
+                          snapshot=0\n\
+                          #-----------\n\
+                          time=0\n\
+                          mem_heap_B=0\n\
+                          mem_heap_extra_B=0\n\
+                          mem_stacks_B=0\n\
+                          heap_tree=empty\n\
+                          #-----------\n\
+                          snapshot=1\n\
+                          #-----------\n\
+                          time=1000\n\
+                          mem_heap_B=1500\n\
+                          mem_heap_extra_B=20\n\
+                          mem_stacks_B=0\n\
+                          heap_tree=peak\n\
+                          n3: 1500 (heap allocation functions) malloc/new/new[], --alloc-fns, etc.\n\
+                          \x20n2: 1400 0x10A2B3: alloc::alloc::alloc (alloc.rs:87)\n\
+                          \x20\x20n1: 1000 0x10A2C0: foo::build::h0123456789abcdef (main.rs:12)\n\
+                          \x20\x20\x20n1: 1000 0x10A2C0: foo::build::h0123456789abcdef (main.rs:14)\n\
+                          \x20\x20\x20\x20n0: 1000 0x10A2D0: foo::main::hfedcba9876543210 (main.rs:3)\n\
+                          \x20\x20n0: 400 0x10A2E0: foo::main::hfedcba9876543210 (main.rs:4)\n\
+                          \x20n0: 60 0x10A300: alloc::raw_vec::finish_grow (raw_vec.rs:5)\n\
+                          \x20n0: 40 in 3 places, all below massif's threshold (1.00%)\n\
+                          #-----------\n\
+                          snapshot=2\n\
+                          #-----------\n\
+                          time=2000\n\
+                          mem_heap_B=500\n\
+                          mem_heap_extra_B=8\n\
+                          mem_stacks_B=0\n\
+                          heap_tree=empty\n";
+
+    #[test]
+    fn test_massif_parse() {
+        let results = parse(OUTPUT, 10).unwrap();
+        assert_eq!(results.time_unit, "i");
+        assert_eq!(results.snapshots.len(), 3);
+        assert_eq!(results.peak, 1);
+        assert_eq!(results.snapshots[1].heap, 1500.);
+        assert!(results.snapshots[1].detailed);
+        // allocations go to the first frame outside the standard library, and to the
+        // allocation function if there is none
+        assert_eq!(
+            results.functs,
+            vec![
+                "main.rs:foo::build",
+                "main.rs:foo::main",
+                "raw_vec.rs:alloc::raw_vec::finish_grow"
+            ]
+        );
+        assert_eq!(results.bytes, vec![1000., 400., 60.]);
+
+        let results = parse(OUTPUT, 1).unwrap();
+        assert_eq!(results.functs.len(), 1);
+        assert!(parse("cmd: foo\n", 10).is_err());
+    }
+
+    #[test]
+    fn test_massif_parse_no_peak() {
+        let output = "snapshot=0\ntime=0\nmem_heap_B=10\nheap_tree=empty\n\
+                      snapshot=1\ntime=5\nmem_heap_B=30\nheap_tree=empty\n\
+                      snapshot=2\ntime=9\nmem_heap_B=20\nheap_tree=empty\n";
+        let results = parse(output, 10).unwrap();
+        assert_eq!(results.peak, 1);
+        assert!(results.functs.is_empty());
+    }
+}
@@ -0,0 +1,213 @@
+use crate::err::ProfError;
+use crate::parse::xml;
+use crate::profiler::{CrateErrors, MemcheckResults, ValgrindError};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+
+/// Leak kinds in the order they are reported, with the label memcheck uses for them.
+pub const LEAK_KINDS: [(&str, &str); 4] = [
+    ("Leak_DefinitelyLost", "definitely lost"),
+    ("Leak_IndirectlyLost", "indirectly lost"),
+    ("Leak_PossiblyLost", "possibly lost"),
+    ("Leak_StillReachable", "still reachable"),
+];
+
+/// Whether an error makes the run fail. Like memcheck's default
+/// `--errors-for-leak-kinds=definite,possible`, indirect and reachable leaks do not.
+pub fn is_failure(error: &ValgrindError) -> bool {
+    error.kind != "Leak_IndirectlyLost" && error.kind != "Leak_StillReachable"
+}
+
+/// Number of `unsafe` keywords in a source file, not counting those in line comments.
+pub fn count_unsafe(source: &str) -> usize {
+    lazy_static! {
+        static ref UNSAFE: Regex = Regex::new(r"\bunsafe\b").unwrap();
+    }
+    source
+        .lines()
+        .map(|x| x.split("//").next().unwrap_or(""))
+        .map(|x| UNSAFE.find_iter(x).count())
+        .sum()
+}
+
+/// Group the errors by the crate they are blamed on, most errors first. `read` returns the
+/// contents of a source file, if it can be found, to count the `unsafe` code in the files
+/// of each crate that show up in the stacks.
+fn crates<F>(errors: &[ValgrindError], read: F) -> Vec<CrateErrors>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for error in errors.iter().filter(|x| is_failure(x)) {
+        let krate = error.site().map_or("", |x| x.krate());
+        if !krate.is_empty() {
+            *counts.entry(krate).or_insert(0) += error.count;
+        }
+    }
+
+    let mut files: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+    for frame in errors
+        .iter()
+        .flat_map(|x| x.stacks.iter())
+        .flat_map(|x| x.frames.iter())
+    {
+        let file = frame.file.as_ref();
+        if let Some(file) = file.filter(|_| counts.contains_key(frame.krate())) {
+            files.entry(frame.krate()).or_default().insert(file);
+        }
+    }
+
+    let mut crates = counts
+        .into_iter()
+        .map(|(name, errors)| CrateErrors {
+            name: name.to_string(),
+            errors,
+            unsafe_count: files
+                .get(name)
+                .into_iter()
+                .flatten()
+                .filter_map(|x| read(x))
+                .map(|x| count_unsafe(&x))
+                .sum(),
+        })
+        .collect::<Vec<_>>();
+    crates.sort_by(|a, b| b.errors.cmp(&a.errors).then_with(|| a.name.cmp(&b.name)));
+    crates
+}
+
+fn summarize<F>(
+    errors: Vec<ValgrindError>,
+    num: usize,
+    read: F,
+) -> Result<MemcheckResults, ProfError>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let failures = errors
+        .iter()
+        .filter(|x| is_failure(x))
+        .map(|x| x.count)
+        .sum();
+    let leaks = LEAK_KINDS
+        .iter()
+        .filter_map(|&(kind, _)| {
+            let records = errors.iter().filter(|x| x.kind == kind).collect::<Vec<_>>();
+            if records.is_empty() {
+                return None;
+            }
+            let bytes = records.iter().map(|x| x.leaked_bytes).sum();
+            let blocks = records.iter().map(|x| x.leaked_blocks).sum();
+            Some((kind.to_string(), bytes, blocks))
+        })
+        .collect();
+    let crates = crates(&errors, read);
+
+    let mut errors = errors;
+    errors.truncate(num);
+    Ok(MemcheckResults {
+        errors,
+        failures,
+        leaks,
+        crates,
+    })
+}
+
+/// Parse the XML memcheck writes with `--xml=yes`. Errors stay in the order memcheck found
+/// them, followed by the leak records.
+pub fn parse(output: &str, num: usize) -> Result<MemcheckResults, ProfError> {
+    summarize(xml::parse(output)?, num, |x| fs::read_to_string(x).ok())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{count_unsafe, summarize};
+    use crate::parse::xml;
+
+    const OUTPUT: &str = r#"<?xml version="1.0"?>
+<valgrindoutput>
+<protocolversion>4</protocolversion>
+<protocoltool>memcheck</protocoltool>
+<error>
+  <unique>0x0</unique>
+  <kind>InvalidWrite</kind>
+  <what>Invalid write of size 8</what>
+  <stack>
+    <frame><fn>core::ptr::write::h0123456789abcdef</fn><dir>/rustc/library/core/src</dir><file>ptr.rs</file><line>9</line></frame>
+    <frame><fn>foo::ffi::fill::h0123456789abcdef</fn><dir>/src/foo/src</dir><file>ffi.rs</file><line>12</line></frame>
+  </stack>
+</error>
+<error>
+  <unique>0x1</unique>
+  <kind>UninitCondition</kind>
+  <what>Conditional jump or move depends on uninitialised value(s)</what>
+  <stack>
+    <frame><fn>bar::check::h0123456789abcdef</fn><dir>/src/bar/src</dir><file>lib.rs</file><line>3</line></frame>
+  </stack>
+</error>
+<error>
+  <unique>0x2</unique>
+  <kind>Leak_DefinitelyLost</kind>
+  <xwhat><text>16 bytes in 1 blocks are definitely lost</text><leakedbytes>16</leakedbytes><leakedblocks>1</leakedblocks></xwhat>
+  <stack>
+    <frame><fn>malloc</fn><obj>/usr/lib/valgrind/vgpreload_memcheck-amd64-linux.so</obj></frame>
+    <frame><fn>foo::ffi::leak::h0123456789abcdef</fn><dir>/src/foo/src</dir><file>ffi.rs</file><line>20</line></frame>
+  </stack>
+</error>
+<error>
+  <unique>0x3</unique>
+  <kind>Leak_StillReachable</kind>
+  <xwhat><text>32 bytes in 2 blocks are still reachable</text><leakedbytes>32</leakedbytes><leakedblocks>2</leakedblocks></xwhat>
+  <stack>
+    <frame><fn>malloc</fn></frame>
+    <frame><fn>bar::init::h0123456789abcdef</fn></frame>
+  </stack>
+</error>
+<errorcounts>
+  <pair><count>4</count><unique>0x0</unique></pair>
+  <pair><count>1</count><unique>0x1</unique></pair>
+</errorcounts>
+</valgrindoutput>
+"#;
+
+    #[test]
+    fn test_memcheck_parse() {
+        let errors = xml::parse(OUTPUT).unwrap();
+        let read = |x: &str| match x {
+            "/src/foo/src/ffi.rs" => {
+                Some("unsafe fn fill() {}\n// unsafe\nunsafe { leak() }".into())
+            }
+            _ => None,
+        };
+        let results = summarize(errors, 2, read).unwrap();
+
+        assert_eq!(results.errors.len(), 2);
+        assert_eq!(results.errors[0].kind, "InvalidWrite");
+        // still reachable blocks are not a failure
+        assert_eq!(results.failures, 6);
+        assert_eq!(
+            results.leaks,
+            vec![
+                ("Leak_DefinitelyLost".to_string(), 16., 1.),
+                ("Leak_StillReachable".to_string(), 32., 2.)
+            ]
+        );
+
+        let crates = results
+            .crates
+            .iter()
+            .map(|x| (&x.name[..], x.errors, x.unsafe_count))
+            .collect::<Vec<_>>();
+        assert_eq!(crates, vec![("foo", 5, 2), ("bar", 1, 0)]);
+    }
+
+    #[test]
+    fn test_count_unsafe() {
+        let source = "unsafe impl Send for Foo {}\n\
+                      fn foo() { unsafe { bar() } } // unsafe here\n\
+                      fn unsafe_name() {}\n";
+        assert_eq!(count_unsafe(source), 2);
+        assert_eq!(count_unsafe(""), 0);
+    }
+}
@@ -1,2 +1,225 @@
 pub mod cachegrind;
 pub mod callgrind;
+pub mod dhat;
+pub mod massif;
+pub mod memcheck;
+pub mod races;
+pub mod xml;
+
+use crate::report::{crate_name, split_label};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// Crates of the standard library. Errors and allocations are blamed on the first frame
+/// outside of them, so that e.g. a `String` clone is attributed to the function that
+/// clones it.
+pub const STD_CRATES: [&str; 4] = ["alloc", "core", "std", "hashbrown"];
+
+/// Whether the function of a `file:function` label belongs to a crate outside the standard
+/// library, i.e. one that errors and allocations can be blamed on.
+pub fn is_user_code(label: &str) -> bool {
+    let krate = crate_name(split_label(label).1);
+    !krate.is_empty() && !STD_CRATES.contains(&krate)
+}
+
+/// Demangle a Rust symbol of the legacy or the v0 scheme, without the hash of legacy
+/// symbols. Valgrind demangles legacy symbols only halfway, e.g. into
+/// `_$LT$std..ops..Range$LT$A$GT$$u20$as$u20$std..iter..Iterator$GT$::next::h0123456789abcdef`,
+/// so those are mangled again and demangled properly. Names that are not Rust symbols are
+/// returned without a hash.
+pub fn demangle(func: &str) -> String {
+    lazy_static! {
+        static ref HASH: Regex = Regex::new(r"::h[0-9a-f]{16}$").unwrap();
+    }
+    if let Ok(demangled) = rustc_demangle::try_demangle(func) {
+        return format!("{:#}", demangled);
+    }
+    if func.contains('$') || func.contains("..") {
+        let mangled = func
+            .split("::")
+            .map(|x| format!("{}{}", x.len(), x))
+            .collect::<String>();
+        if let Ok(demangled) = rustc_demangle::try_demangle(&format!("_ZN{}E", mangled)) {
+            return format!("{:#}", demangled);
+        }
+    }
+    HASH.replace(func, "").into_owned()
+}
+
+/// Drop the module path of standard library items inside angle brackets, so that
+/// `<core::ops::range::Range<usize> as core::iter::traits::iterator::Iterator>::next`
+/// reads `<Range<usize> as Iterator>::next`. Paths outside of them are kept, they tell
+/// which crate a function belongs to.
+fn shorten(func: &str) -> String {
+    lazy_static! {
+        static ref STD_PATH: Regex = Regex::new(r"\b(?:core|alloc|std)::(?:\w+::)*").unwrap();
+    }
+    let mut out = String::new();
+    let mut last = 0;
+    for path in STD_PATH.find_iter(func) {
+        let before = &func[..path.start()];
+        // how deep the path is nested in angle brackets, `->` does not close one
+        let depth = before.matches('<').count() as isize - before.matches('>').count() as isize
+            + before.matches("->").count() as isize;
+        if depth > 0 && !before.ends_with(':') {
+            out.push_str(&func[last..path.start()]);
+            last = path.end();
+        }
+    }
+    out.push_str(&func[last..]);
+    out
+}
+
+/// A function name as we display it: demangled, without hash and with short paths for
+/// standard library types and traits.
+pub fn function(func: &str) -> String {
+    shorten(&demangle(func))
+}
+
+/// Replace the generic arguments in a function name with `<…>`, so that
+/// `alloc::vec::Vec<Box<[u8]>>::push` reads `alloc::vec::Vec<…>::push`. The angle brackets
+/// of qualified paths like `<T as Trait>::f` are kept.
+pub fn collapse_generics(func: &str) -> String {
+    let mut out = String::new();
+    // nesting of the generic arguments being skipped
+    let mut depth = 0;
+    let mut prev = ' ';
+    for c in func.chars() {
+        if depth > 0 {
+            match c {
+                '<' => depth += 1,
+                '>' if prev != '-' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        out.push_str("<…>");
+                    }
+                }
+                _ => {}
+            }
+        } else if c == '<' && (prev.is_alphanumeric() || prev == '_' || prev == ':') {
+            depth = 1;
+        } else {
+            out.push(c);
+        }
+        prev = c;
+    }
+    if depth > 0 {
+        out.push_str("<…>");
+    }
+    out
+}
+
+/// Turn a source file and a function name into the `file:function` label we display.
+pub fn label(file: &str, func: &str) -> String {
+    let file = file.rsplit('/').next().unwrap_or(file);
+    format!("{}:{}", file, function(func))
+}
+
+/// Label of a valgrind stack frame like `0x10A2B3: foo::bar (main.rs:12)` or
+/// `0x4C2DB8F: malloc (in /usr/lib/valgrind/vgpreload_massif.so)`. `None` for entries
+/// without a function, such as the root of a heap tree.
+pub fn site(desc: &str) -> Option<String> {
+    if !desc.starts_with("0x") {
+        return None;
+    }
+    let desc = &desc[desc.find(": ")? + 2..];
+    match desc.rfind(" (") {
+        Some(idx) if desc.ends_with(')') => {
+            let location = &desc[idx + 2..desc.len() - 1];
+            let file = match location.strip_prefix("in ") {
+                Some(object) => object,
+                // `file:line`, newer valgrind versions also give the column
+                None => location.split(':').next().unwrap_or(location),
+            };
+            Some(label(file, &desc[..idx]))
+        }
+        _ => Some(label("???", desc)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{collapse_generics, demangle, function, site};
+
+    #[test]
+    fn test_site() {
+        assert_eq!(
+            site("0x10A2B3: foo::bar::h0123456789abcdef (src/main.rs:12)"),
+            Some("main.rs:foo::bar".to_string())
+        );
+        assert_eq!(
+            site("0x4C2DB8F: malloc (in /usr/lib/valgrind/vgpreload_massif.so)"),
+            Some("vgpreload_massif.so:malloc".to_string())
+        );
+        assert_eq!(
+            site("0x10A2B3: <T as foo::Bar>::baz (lib.rs:3:9)"),
+            Some("lib.rs:<T as foo::Bar>::baz".to_string())
+        );
+        assert_eq!(
+            site("in 3 places, all below massif's threshold (1.00%)"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_demangle() {
+        // legacy, as in the symbol table and as valgrind prints it
+        assert_eq!(
+            demangle("_ZN4core3ptr13drop_in_place17h0123456789abcdefE"),
+            "core::ptr::drop_in_place"
+        );
+        assert_eq!(
+            demangle(
+                "_$LT$std..ops..Range$LT$A$GT$$u20$as$u20$std..iter..Iterator$GT$::next::h0123456789abcdef"
+            ),
+            "<std::ops::Range<A> as std::iter::Iterator>::next"
+        );
+        assert_eq!(demangle("foo::main::h0123456789abcdef"), "foo::main");
+        // v0
+        assert_eq!(
+            demangle("_RNvMsr_NtCs3ssYzQotkvD_3std4pathNtB5_7PathBuf3newCs15kBYyAo9fc_7mycrate"),
+            "<std::path::PathBuf>::new"
+        );
+        assert_eq!(demangle("malloc"), "malloc");
+        assert_eq!(demangle("foo::head"), "foo::head");
+    }
+
+    #[test]
+    fn test_function() {
+        assert_eq!(
+            function(
+                "<core::ops::range::Range<usize> as core::iter::traits::iterator::Iterator>::next"
+            ),
+            "<Range<usize> as Iterator>::next"
+        );
+        assert_eq!(
+            function("<foo::Cache as core::clone::Clone>::clone"),
+            "<foo::Cache as Clone>::clone"
+        );
+        assert_eq!(
+            function("alloc::vec::Vec<alloc::string::String>::push"),
+            "alloc::vec::Vec<String>::push"
+        );
+        assert_eq!(
+            function("foo::apply<fn(u8) -> core::option::Option<u8>>"),
+            "foo::apply<fn(u8) -> Option<u8>>"
+        );
+    }
+
+    #[test]
+    fn test_collapse_generics() {
+        assert_eq!(
+            collapse_generics("alloc::vec::Vec<Box<[u8]>>::push"),
+            "alloc::vec::Vec<…>::push"
+        );
+        assert_eq!(
+            collapse_generics("<Range<usize> as Iterator>::next"),
+            "<Range<…> as Iterator>::next"
+        );
+        assert_eq!(
+            collapse_generics("foo::apply::<fn(u8) -> u8>"),
+            "foo::apply::<…>"
+        );
+        assert_eq!(collapse_generics("main.rs:foo::main"), "main.rs:foo::main");
+    }
+}
@@ -0,0 +1,129 @@
+use crate::err::ProfError;
+use crate::parse::xml;
+use crate::profiler::{RaceResults, ValgrindError};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+/// What identifies a report regardless of addresses and thread ids: its kind and the
+/// code locations of its stacks, in any order so that both sides of a race match.
+fn key(error: &ValgrindError) -> String {
+    let mut stacks = error
+        .stacks
+        .iter()
+        .filter(|x| !x.frames.is_empty())
+        .map(|x| {
+            x.frames
+                .iter()
+                .map(|x| format!("{}:{}", x.label(), x.line.unwrap_or(0)))
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .collect::<Vec<_>>();
+    stacks.sort();
+    format!("{}|{}", error.kind, stacks.join("|"))
+}
+
+/// Merge reports of the same code, e.g. the same race on every element of an array.
+fn dedup(errors: Vec<ValgrindError>) -> Vec<ValgrindError> {
+    let mut index: HashMap<String, usize> = HashMap::new();
+    let mut unique: Vec<ValgrindError> = Vec::new();
+    for error in errors {
+        let key = key(&error);
+        match index.get(&key) {
+            Some(&idx) => unique[idx].count += error.count,
+            None => {
+                index.insert(key, unique.len());
+                unique.push(error);
+            }
+        }
+    }
+    unique
+}
+
+/// Parse the XML helgrind or drd write with `--xml=yes`. Duplicate reports are merged and
+/// the most frequent come first.
+pub fn parse(output: &str, num: usize, tool: &str) -> Result<RaceResults, ProfError> {
+    let errors = xml::parse(output)?;
+    let failures = errors.iter().map(|x| x.count).sum();
+
+    let mut errors = dedup(errors);
+    let distinct = errors.len();
+    errors.sort_by_key(|x| Reverse(x.count));
+    errors.truncate(num);
+
+    Ok(RaceResults {
+        tool: tool.to_string(),
+        errors,
+        failures,
+        distinct,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse;
+
+    const OUTPUT: &str = r#"<?xml version="1.0"?>
+<valgrindoutput>
+<protocolversion>4</protocolversion>
+<protocoltool>helgrind</protocoltool>
+<error>
+  <unique>0x0</unique>
+  <kind>Race</kind>
+  <xwhat><text>Possible data race during write of size 4 at 0x10C04C by thread #3</text><hthreadid>3</hthreadid></xwhat>
+  <stack>
+    <frame><fn>foo::bump::h0123456789abcdef</fn><dir>/src/foo/src</dir><file>main.rs</file><line>8</line></frame>
+  </stack>
+  <xauxwhat><text>This conflicts with a previous read of size 4 by thread #2</text><hthreadid>2</hthreadid></xauxwhat>
+  <stack>
+    <frame><fn>foo::get::h0123456789abcdef</fn><dir>/src/foo/src</dir><file>main.rs</file><line>4</line></frame>
+  </stack>
+  <auxwhat>Address 0x10C04C is 0 bytes inside data symbol "COUNTER"</auxwhat>
+</error>
+<error>
+  <unique>0x1</unique>
+  <kind>Race</kind>
+  <xwhat><text>Possible data race during read of size 4 at 0x10C050 by thread #2</text><hthreadid>2</hthreadid></xwhat>
+  <stack>
+    <frame><fn>foo::get::h0123456789abcdef</fn><dir>/src/foo/src</dir><file>main.rs</file><line>4</line></frame>
+  </stack>
+  <xauxwhat><text>This conflicts with a previous write of size 4 by thread #3</text><hthreadid>3</hthreadid></xauxwhat>
+  <stack>
+    <frame><fn>foo::bump::h0123456789abcdef</fn><dir>/src/foo/src</dir><file>main.rs</file><line>8</line></frame>
+  </stack>
+</error>
+<error>
+  <unique>0x2</unique>
+  <kind>UnlockUnlocked</kind>
+  <what>Thread #1 unlocked a not-locked lock at 0x10C060</what>
+  <stack>
+    <frame><fn>pthread_mutex_unlock</fn><obj>/usr/lib/valgrind/vgpreload_helgrind-amd64-linux.so</obj></frame>
+  </stack>
+</error>
+<errorcounts>
+  <pair><count>1</count><unique>0x0</unique></pair>
+  <pair><count>1</count><unique>0x1</unique></pair>
+  <pair><count>1</count><unique>0x2</unique></pair>
+</errorcounts>
+</valgrindoutput>
+"#;
+
+    #[test]
+    fn test_races_parse() {
+        let results = parse(OUTPUT, 10, "helgrind").unwrap();
+        assert_eq!(results.tool, "helgrind");
+        assert_eq!((results.failures, results.distinct), (3, 2));
+
+        // the two sides of the race are one report
+        assert_eq!(results.errors.len(), 2);
+        let race = &results.errors[0];
+        assert_eq!((race.kind.as_str(), race.count), ("Race", 2));
+        assert_eq!(race.stacks.len(), 3);
+        assert_eq!(race.stacks[0].frames[0].func, "foo::bump");
+        assert_eq!(race.stacks[1].frames[0].func, "foo::get");
+        assert_eq!(results.errors[1].kind, "UnlockUnlocked");
+
+        let results = parse(OUTPUT, 1, "helgrind").unwrap();
+        assert_eq!((results.errors.len(), results.failures), (1, 3));
+    }
+}
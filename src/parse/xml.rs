@@ -0,0 +1,292 @@
+use crate::err::ProfError;
+use crate::parse::{function, STD_CRATES};
+use crate::profiler::{Frame, Stack, ValgrindError};
+use crate::report::crate_name;
+use roxmltree::{Document, Node};
+use std::collections::HashMap;
+
+impl Frame {
+    /// `file:function` label of the frame, with the object instead of the file for code
+    /// without debug info.
+    pub fn label(&self) -> String {
+        let file = self.file.as_ref().or(self.obj.as_ref());
+        let file = file.map_or("???", |x| x.rsplit('/').next().unwrap_or(x));
+        format!("{}:{}", file, self.func)
+    }
+
+    /// Crate the function of the frame belongs to, empty for C functions.
+    pub fn krate(&self) -> &str {
+        crate_name(&self.func)
+    }
+}
+
+impl ValgrindError {
+    /// Frame the error is blamed on: the first frame where it happened that is not in the
+    /// standard library or C code, falling back to the innermost frame.
+    pub fn site(&self) -> Option<&Frame> {
+        let frames = &self.stacks.first()?.frames;
+        frames
+            .iter()
+            .find(|x| !x.krate().is_empty() && !STD_CRATES.contains(&x.krate()))
+            .or_else(|| frames.first())
+    }
+}
+
+/// Text of the first child element called `name`.
+fn child<'a>(node: Node<'a, 'a>, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|x| x.has_tag_name(name))
+        .and_then(|x| x.text())
+}
+
+fn frame(node: Node) -> Frame {
+    let file = match (child(node, "dir"), child(node, "file")) {
+        (Some(dir), Some(file)) => Some(format!("{}/{}", dir, file)),
+        (None, Some(file)) => Some(file.to_string()),
+        _ => None,
+    };
+    Frame {
+        func: function(child(node, "fn").unwrap_or("???")),
+        file,
+        line: child(node, "line").and_then(|x| x.parse().ok()),
+        obj: child(node, "obj").map(|x| x.to_string()),
+    }
+}
+
+fn frames(node: Node) -> Vec<Frame> {
+    node.children()
+        .filter(|x| x.has_tag_name("frame"))
+        .map(frame)
+        .collect()
+}
+
+/// Description of a `what`/`auxwhat` element or the `text` of an `xwhat`/`xauxwhat`.
+fn description(node: Node) -> String {
+    node.text()
+        .filter(|x| !x.trim().is_empty())
+        .or_else(|| child(node, "text"))
+        .unwrap_or("")
+        .trim()
+        .to_string()
+}
+
+fn error(node: Node, counts: &HashMap<&str, usize>) -> ValgrindError {
+    let mut stacks: Vec<Stack> = Vec::new();
+    // description waiting for its stack
+    let mut pending: Option<String> = None;
+    let mut leaked_bytes = 0.;
+    let mut leaked_blocks = 0.;
+
+    for x in node.children().filter(|x| x.is_element()) {
+        match x.tag_name().name() {
+            "what" | "xwhat" | "auxwhat" | "xauxwhat" => {
+                if let Some(title) = pending.take() {
+                    stacks.push(Stack {
+                        title,
+                        frames: vec![],
+                    });
+                }
+                pending = Some(description(x));
+                if let Some(bytes) = child(x, "leakedbytes").and_then(|x| x.parse().ok()) {
+                    leaked_bytes = bytes;
+                }
+                if let Some(blocks) = child(x, "leakedblocks").and_then(|x| x.parse().ok()) {
+                    leaked_blocks = blocks;
+                }
+            }
+            "stack" => stacks.push(Stack {
+                title: pending.take().unwrap_or_default(),
+                frames: frames(x),
+            }),
+            // drd explains conflicting accesses with the segment of the other thread
+            name @ "other_segment_start" | name @ "other_segment_end" => {
+                if let Some(stack) = x.children().find(|x| x.has_tag_name("stack")) {
+                    stacks.push(Stack {
+                        title: name.replace('_', " "),
+                        frames: frames(stack),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(title) = pending {
+        stacks.push(Stack {
+            title,
+            frames: vec![],
+        });
+    }
+
+    let unique = child(node, "unique").unwrap_or("");
+    ValgrindError {
+        kind: child(node, "kind").unwrap_or("???").to_string(),
+        stacks,
+        // leak records are not in the error counts, every record is reported once
+        count: counts.get(unique).cloned().unwrap_or(1),
+        leaked_bytes,
+        leaked_blocks,
+    }
+}
+
+/// Parse the errors of valgrind's XML output (`--xml=yes`, protocol version 4), which
+/// memcheck, helgrind and drd all write.
+pub fn parse(output: &str) -> Result<Vec<ValgrindError>, ProfError> {
+    let doc = Document::parse(output)
+        .map_err(|e| ProfError::MalformedOutput(format!("bad valgrind XML: {}", e)))?;
+    let root = doc.root_element();
+    if !root.has_tag_name("valgrindoutput") {
+        return Err(ProfError::MalformedOutput(
+            "no valgrindoutput element".to_string(),
+        ));
+    }
+
+    // how often every error occurred, by its unique id
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for pair in root
+        .children()
+        .filter(|x| x.has_tag_name("errorcounts"))
+        .flat_map(|x| x.children())
+        .filter(|x| x.has_tag_name("pair"))
+    {
+        if let (Some(unique), Some(count)) = (child(pair, "unique"), child(pair, "count")) {
+            counts.insert(unique, count.parse().unwrap_or(1));
+        }
+    }
+
+    Ok(root
+        .children()
+        .filter(|x| x.has_tag_name("error"))
+        .map(|x| error(x, &counts))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse;
+
+    const OUTPUT: &str = r#"<?xml version="1.0"?>
+<valgrindoutput>
+<protocolversion>4</protocolversion>
+<protocoltool>memcheck</protocoltool>
+<error>
+  <unique>0x0</unique>
+  <tid>1</tid>
+  <kind>InvalidRead</kind>
+  <what>Invalid read of size 4</what>
+  <stack>
+    <frame>
+      <ip>0x10A2B3</ip>
+      <obj>/tmp/foo</obj>
+      <fn>foo::ffi::read::h0123456789abcdef</fn>
+      <dir>/src/foo/src</dir>
+      <file>ffi.rs</file>
+      <line>12</line>
+    </frame>
+    <frame>
+      <ip>0x10A2D0</ip>
+      <obj>/tmp/foo</obj>
+      <fn>foo::main::hfedcba9876543210</fn>
+    </frame>
+  </stack>
+  <auxwhat>Address 0x4a4b040 is 0 bytes after a block of size 16 alloc'd</auxwhat>
+  <stack>
+    <frame>
+      <ip>0x4C2DB8F</ip>
+      <obj>/usr/lib/valgrind/vgpreload_memcheck-amd64-linux.so</obj>
+      <fn>malloc</fn>
+    </frame>
+  </stack>
+</error>
+<error>
+  <unique>0x1</unique>
+  <tid>1</tid>
+  <kind>Leak_DefinitelyLost</kind>
+  <xwhat>
+    <text>16 bytes in 2 blocks are definitely lost in loss record 1 of 1</text>
+    <leakedbytes>16</leakedbytes>
+    <leakedblocks>2</leakedblocks>
+  </xwhat>
+  <stack>
+    <frame><ip>0x1</ip><fn>malloc</fn></frame>
+  </stack>
+</error>
+<errorcounts>
+  <pair><count>3</count><unique>0x0</unique></pair>
+</errorcounts>
+</valgrindoutput>
+"#;
+
+    #[test]
+    fn test_xml_parse() {
+        let errors = parse(OUTPUT).unwrap();
+        assert_eq!(errors.len(), 2);
+
+        let read = &errors[0];
+        assert_eq!((read.kind.as_str(), read.count), ("InvalidRead", 3));
+        assert_eq!(read.stacks.len(), 2);
+        assert_eq!(read.stacks[0].title, "Invalid read of size 4");
+        assert_eq!(read.stacks[0].frames[0].func, "foo::ffi::read");
+        assert_eq!(
+            read.stacks[0].frames[0].file.as_deref(),
+            Some("/src/foo/src/ffi.rs")
+        );
+        assert_eq!(read.stacks[0].frames[0].line, Some(12));
+        assert_eq!(read.stacks[0].frames[1].file, None);
+        assert_eq!(read.stacks[1].frames[0].func, "malloc");
+        assert_eq!(read.site().unwrap().label(), "ffi.rs:foo::ffi::read");
+        assert_eq!(
+            read.stacks[1].frames[0].label(),
+            "vgpreload_memcheck-amd64-linux.so:malloc"
+        );
+
+        let leak = &errors[1];
+        assert_eq!(
+            (leak.count, leak.leaked_bytes, leak.leaked_blocks),
+            (1, 16., 2.)
+        );
+        assert_eq!(
+            leak.stacks[0].title,
+            "16 bytes in 2 blocks are definitely lost in loss record 1 of 1"
+        );
+
+        assert_eq!(leak.site().unwrap().label(), "???:malloc");
+
+        assert!(parse("<foo/>").is_err());
+        assert!(parse("not xml").is_err());
+    }
+
+    #[test]
+    fn test_xml_parse_drd() {
+        let output = r#"<valgrindoutput>
+<error>
+  <unique>0x0</unique>
+  <kind>ConflictingAccess</kind>
+  <what>Conflicting load by thread 2 at 0x0010c04c size 4</what>
+  <stack><frame><fn>foo::get</fn></frame></stack>
+  <other_segment_start>
+    <stack><frame><fn>foo::bump</fn></frame></stack>
+  </other_segment_start>
+  <other_segment_end>
+    <stack><frame><fn>foo::done</fn></frame></stack>
+  </other_segment_end>
+</error>
+</valgrindoutput>"#;
+        let errors = parse(output).unwrap();
+        let titles = errors[0]
+            .stacks
+            .iter()
+            .map(|x| (&x.title[..], &x.frames[0].func[..]))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            titles,
+            vec![
+                (
+                    "Conflicting load by thread 2 at 0x0010c04c size 4",
+                    "foo::get"
+                ),
+                ("other segment start", "foo::bump"),
+                ("other segment end", "foo::done")
+            ]
+        );
+    }
+}
@@ -1,74 +1,166 @@
+use crate::callgraph::CallGraph;
 use ndarray::Array2;
-use std::f64;
 
-// Profiler enum. We have two profilers: CacheGrind and CallGrind.
-pub enum Profiler {
-    // CachGrind holds the parsed objects of
-    // `valgrind --tool=cachegrind -cachegrind-out-file=cachegrind.out
-    // && cg_annotate cachegrind.out`
-    CacheGrind {
-        ir: f64,
-        i1mr: f64,
-        ilmr: f64,
-        dr: f64,
-        d1mr: f64,
-        dlmr: f64,
-        dw: f64,
-        d1mw: f64,
-        dlmw: f64,
-        data: Array2<f64>,
-        functs: Vec<String>,
-    },
+// CacheGrindResults holds the parsed objects of
+// `valgrind --tool=cachegrind --cachegrind-out-file=cachegrind.out`
+pub struct CacheGrindResults {
+    // names of the counted events, from the `events:` line
+    pub events: Vec<String>,
+    // total count of every event
+    pub totals: Vec<f64>,
+    // profiler data, one row per function
+    pub data: Array2<f64>,
+    // profiled functions in binary
+    pub functs: Vec<String>,
+    // event the functions are sorted by
+    pub metric: String,
+}
+
+// CallGrindResults holds the parsed objects of
+// `valgrind --tool=callgrind --callgrind-out-file=callgrind.out`
+pub struct CallGrindResults {
+    // total instruction calls
+    pub total_instructions: f64,
+    // instructions executed in each function itself
+    pub instructions: Vec<f64>,
+    // instructions executed in each function and everything it calls
+    pub inclusive: Vec<f64>,
+    // profiled functions in binary
+    pub functs: Vec<String>,
+    // callers and callees of all profiled functions
+    pub graph: CallGraph,
+    // `Ir` or `Ir_inclusive`, whichever the functions are sorted by
+    pub metric: String,
+}
+
+// One point in time of a massif profile
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    // time in the time unit of the profile
+    pub time: f64,
+    // bytes requested by the program
+    pub heap: f64,
+    // bytes of allocator overhead and alignment
+    pub extra: f64,
+    // bytes on the stacks, only measured with --stacks=yes
+    pub stacks: f64,
+    // whether the snapshot has a heap tree
+    pub detailed: bool,
+}
+
+// MassifResults holds the parsed objects of
+// `valgrind --tool=massif --massif-out-file=massif.out`
+pub struct MassifResults {
+    // `i` (instructions), `ms` or `B` (bytes allocated and freed)
+    pub time_unit: String,
+    // heap usage over time
+    pub snapshots: Vec<Snapshot>,
+    // index of the snapshot with the most heap in use
+    pub peak: usize,
+    // functions allocating the heap in use at the peak
+    pub functs: Vec<String>,
+    // bytes each function allocated at the peak, not counting the standard library
+    pub bytes: Vec<f64>,
+}
+
+// Allocations of one site of a dhat profile, summed over all call stacks that end there
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AllocationSite {
+    // `file:function` of the first frame outside the allocator and the standard library
+    pub funct: String,
+    // bytes allocated
+    pub bytes: f64,
+    // blocks allocated
+    pub blocks: f64,
+    // sum of the lifetimes of all blocks, in the time unit of the profile
+    pub lifetime: f64,
+    // bytes read from and written to the blocks, unless dhat ran with --mode=copy/ad-hoc
+    pub reads: Option<f64>,
+    pub writes: Option<f64>,
+}
 
-    // Call holds the parsed objects of
-    // `valgrind --tool=callgrind --callgrind-out-file=callgrind.out
-    // && callgrind_annotate callgrind.out`
-    CallGrind {
-        total_instructions: f64,
-        instructions: Vec<f64>,
-        functs: Vec<String>,
-    },
+// DhatResults holds the parsed objects of
+// `valgrind --tool=dhat --dhat-out-file=dhat.out`
+pub struct DhatResults {
+    // `instrs` or the unit of the ad-hoc mode
+    pub time_unit: String,
+    // bytes and blocks allocated by the whole program
+    pub total_bytes: f64,
+    pub total_blocks: f64,
+    // bytes live at the global heap peak
+    pub peak_bytes: f64,
+    // the first `num` allocation sites, sorted by `metric`
+    pub sites: Vec<AllocationSite>,
+    // metric the sites are sorted by
+    pub metric: String,
 }
 
-// Initialize the Profilers
-impl Profiler {
-    // Initialize CacheGrind
+// One frame of a stack trace in valgrind's XML output
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Frame {
+    // function name, without the hash rustc appends
+    pub func: String,
+    // path of the source file, if the binary has debug info
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    // object the code is in, e.g. a shared library
+    pub obj: Option<String>,
+}
+
+// A stack trace and what happened there, e.g. `Invalid read of size 4`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stack {
+    pub title: String,
+    // innermost frame first, empty for notes like `Address 0x0 is not stack'd`
+    pub frames: Vec<Frame>,
+}
+
+// An error valgrind reported, with all its stacks
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ValgrindError {
+    // e.g. `InvalidRead`, `Leak_DefinitelyLost` or `Race`
+    pub kind: String,
+    // the first stack is where the error happened, the others explain it
+    pub stacks: Vec<Stack>,
+    // how often the error occurred
+    pub count: usize,
+    // bytes and blocks lost, for leak records
+    pub leaked_bytes: f64,
+    pub leaked_blocks: f64,
+}
+
+// Errors and unsafe code of one crate
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CrateErrors {
+    pub name: String,
+    // errors that happened in the crate's code
+    pub errors: usize,
+    // `unsafe` blocks, functions and impls in the crate's files that show up in stacks
+    pub unsafe_count: usize,
+}
+
+// MemcheckResults holds the parsed objects of
+// `valgrind --tool=memcheck --xml=yes --xml-file=memcheck.xml`
+pub struct MemcheckResults {
+    // the first `num` errors and leak records
+    pub errors: Vec<ValgrindError>,
+    // errors that count as failures: everything but indirect and still reachable leaks
+    pub failures: usize,
+    // bytes and blocks lost per leak kind, e.g. `Leak_DefinitelyLost`
+    pub leaks: Vec<(String, f64, f64)>,
+    // crates the errors happened in, most errors first
+    pub crates: Vec<CrateErrors>,
+}
 
-    pub fn new_cachegrind() -> Profiler {
-        Profiler::CacheGrind {
-            // total instructions
-            ir: f64::NAN,
-            // total instruction-cache read misses
-            i1mr: f64::NAN,
-            // total LL-cache read misses
-            ilmr: f64::NAN,
-            // total reads
-            dr: f64::NAN,
-            // total data-cache read misses
-            d1mr: f64::NAN,
-            // total LL-cache read misses
-            dlmr: f64::NAN,
-            // total data-cache writes
-            dw: f64::NAN,
-            // total data-cache write-misses
-            d1mw: f64::NAN,
-            // total LL cache write misses
-            dlmw: f64::NAN,
-            // profiler data
-            data: Array2::zeros((2, 2)),
-            // profiled functions in binary
-            functs: Vec::new(),
-        }
-    }
-    // Initialize CallGrind
-    pub fn new_callgrind() -> Profiler {
-        Profiler::CallGrind {
-            // total instruction calls
-            total_instructions: f64::NAN,
-            // instruction data
-            instructions: Vec::new(),
-            // profiled functions in binary
-            functs: Vec::new(),
-        }
-    }
+// RaceResults holds the parsed objects of
+// `valgrind --tool=helgrind --xml=yes --xml-file=helgrind.xml` or the same for drd
+pub struct RaceResults {
+    // `helgrind` or `drd`
+    pub tool: String,
+    // the first `num` distinct reports, most frequent first
+    pub errors: Vec<ValgrindError>,
+    // errors reported in total, counting duplicates
+    pub failures: usize,
+    // distinct reports, before keeping the first `num`
+    pub distinct: usize,
 }
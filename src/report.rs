@@ -0,0 +1,409 @@
+use crate::cache::geometry;
+use crate::err::ProfError;
+use crate::parse::{demangle, label};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::process::Command;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Version of the JSON schema. Bumped whenever a field is removed or changes its meaning.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Format the results are printed in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+    Tsv,
+}
+
+/// What the costs of functions are added up by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GroupBy {
+    Crate,
+    Module,
+    File,
+    #[default]
+    Function,
+}
+
+impl FromStr for GroupBy {
+    type Err = ProfError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "crate" => Ok(GroupBy::Crate),
+            "module" => Ok(GroupBy::Module),
+            "file" => Ok(GroupBy::File),
+            "function" => Ok(GroupBy::Function),
+            _ => Err(ProfError::InvalidGroupBy(s.to_string())),
+        }
+    }
+}
+
+impl GroupBy {
+    /// Label of the row the costs of the raw function name `func` in `file` go to: the
+    /// `file:function` label, or the name of the crate, module or file. Functions without
+    /// a crate or module, such as C functions, go to `???`.
+    pub fn label(self, file: &str, func: &str) -> String {
+        let known = |x: &str| if x.is_empty() { "???" } else { x }.to_string();
+        match self {
+            GroupBy::Function => label(file, func),
+            GroupBy::File => file.rsplit('/').next().unwrap_or(file).to_string(),
+            GroupBy::Crate => known(crate_name(&demangle(func))),
+            GroupBy::Module => known(module_name(&demangle(func))),
+        }
+    }
+}
+
+/// Information about the profiled run.
+#[derive(Serialize, Deserialize)]
+pub struct Metadata {
+    /// path of the profiled binary
+    pub binary: String,
+    /// arguments the binary was run with
+    pub args: Vec<String>,
+    /// output of `valgrind --version`, e.g. `valgrind-3.15.0`
+    pub valgrind_version: Option<String>,
+    /// extra arguments valgrind was run with, e.g. from `--cache-config`
+    #[serde(default)]
+    pub valgrind_args: Vec<String>,
+    /// simulated cache geometry, e.g. `LL` => `8388608 B, 64 B, 16-way associative`
+    #[serde(default)]
+    pub cache: BTreeMap<String, String>,
+    /// seconds since the unix epoch when the report was created
+    pub timestamp: u64,
+}
+
+impl Metadata {
+    pub fn new(binary: &str, args: &[String]) -> Metadata {
+        let valgrind_version = Command::new("valgrind")
+            .arg("--version")
+            .output()
+            .ok()
+            .and_then(|x| String::from_utf8(x.stdout).ok())
+            .map(|x| x.trim().to_string());
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or(0);
+
+        Metadata {
+            binary: binary.to_string(),
+            args: args.to_vec(),
+            valgrind_version,
+            valgrind_args: vec![],
+            cache: BTreeMap::new(),
+            timestamp,
+        }
+    }
+
+    /// Record the extra valgrind arguments and the cache geometry from the profile.
+    pub fn with_valgrind(mut self, valgrind_args: &[String], output: &str) -> Metadata {
+        self.valgrind_args = valgrind_args.to_vec();
+        self.cache = geometry(output);
+        self
+    }
+
+    /// Metadata that does not depend on the machine the tests run on.
+    #[cfg(test)]
+    pub(crate) fn fixture(binary: &str, args: &[&str]) -> Metadata {
+        Metadata {
+            binary: binary.to_string(),
+            args: args.iter().map(|x| x.to_string()).collect(),
+            valgrind_version: None,
+            valgrind_args: vec![],
+            cache: Default::default(),
+            timestamp: 0,
+        }
+    }
+}
+
+/// Report of all functions of a callgrind profile, for the tests of baselines and budgets.
+#[cfg(test)]
+pub(crate) fn fixture(output: &str) -> Report {
+    use crate::backend::Results;
+
+    let results = crate::parse::callgrind::parse(output, usize::MAX, false, GroupBy::Function)
+        .expect("invalid callgrind fixture");
+    results.report(Metadata::fixture("foo", &[]))
+}
+
+/// Costs of a single function.
+#[derive(Serialize, Deserialize)]
+pub struct Function {
+    /// function name
+    pub name: String,
+    /// name of the source file the function is defined in
+    pub file: String,
+    /// cost of the function for every event of the report
+    pub metrics: BTreeMap<String, f64>,
+}
+
+/// Profiler results in a form that is stable across versions and easy to serialize.
+#[derive(Serialize, Deserialize)]
+pub struct Report {
+    pub schema_version: u32,
+    /// name of the backend, e.g. `callgrind` or `cachegrind`
+    pub profiler: String,
+    pub metadata: Metadata,
+    /// names of the metrics of every function, in display order
+    pub events: Vec<String>,
+    /// total cost of the whole program per event
+    pub totals: BTreeMap<String, f64>,
+    /// profiled functions, most expensive first
+    pub functions: Vec<Function>,
+}
+
+/// Split a `file:function` label into its file and function. Labels of grouped rows, such
+/// as `serde_json::de`, only have a name.
+pub fn split_label(label: &str) -> (&str, &str) {
+    let bytes = label.as_bytes();
+    let colon = (0..bytes.len()).find(|&i| {
+        bytes[i] == b':' && bytes.get(i + 1) != Some(&b':') && (i == 0 || bytes[i - 1] != b':')
+    });
+    match colon {
+        Some(idx) => (&label[..idx], &label[idx + 1..]),
+        None => ("???", label),
+    }
+}
+
+/// Name of the crate a function belongs to, i.e. the first segment of its path or, for
+/// methods of trait impls like `<foo::Bar as Clone>::clone`, of the path of the type. Empty
+/// for functions without a path, such as C functions and impls for standard library types.
+pub fn crate_name(func: &str) -> &str {
+    let func = func.trim_start_matches(['<', '&', '*']);
+    let path = &func[..func.find([' ', '<', '>', ';']).unwrap_or(func.len())];
+    match path.find("::") {
+        Some(idx) => &path[..idx],
+        None => "",
+    }
+}
+
+/// Module a function is defined in, i.e. its path without the function and any types:
+/// `serde_json::de` for `serde_json::de::Deserializer<R>::parse_value`. Methods of trait
+/// impls belong to the module of their type. Empty for functions without a path.
+pub fn module_name(func: &str) -> &str {
+    let func = func.trim_start_matches(['<', '&', '*']);
+    let path = &func[..func
+        .find([' ', '<', '>', ';', '{', '('])
+        .unwrap_or(func.len())];
+    let mut segments = path
+        .split("::")
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>();
+    // the last segment is a function unless generics or a closure follow after `::`
+    if !path.ends_with("::") && segments.last().is_some_and(|x| !is_type(x)) {
+        segments.pop();
+    }
+    let modules = segments.iter().take_while(|x| !is_type(x)).count();
+    let len = segments[..modules]
+        .iter()
+        .map(|x| x.len() + 2)
+        .sum::<usize>();
+    &path[..len.saturating_sub(2)]
+}
+
+/// Whether a path segment names a type, which are CamelCase, rather than a module.
+fn is_type(segment: &str) -> bool {
+    segment.starts_with(|c: char| c.is_uppercase())
+}
+
+/// Quote a CSV/TSV field if it contains the separator, quotes or line breaks.
+fn quote(field: &str, sep: char) -> String {
+    if field.contains([sep, '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// A function from its `file:function` label and its costs.
+pub fn function(label: &str, metrics: BTreeMap<String, f64>) -> Function {
+    let (file, name) = split_label(label);
+    Function {
+        name: name.to_string(),
+        file: file.to_string(),
+        metrics,
+    }
+}
+
+impl Report {
+    pub fn new(
+        profiler: &str,
+        metadata: Metadata,
+        events: Vec<String>,
+        totals: BTreeMap<String, f64>,
+        functions: Vec<Function>,
+    ) -> Report {
+        Report {
+            schema_version: SCHEMA_VERSION,
+            profiler: profiler.to_string(),
+            metadata,
+            events,
+            totals,
+            functions,
+        }
+    }
+
+    /// Write the functions as a table with one row per function, separated by `sep`.
+    /// Every event gets a column with its absolute count, and events with a total also
+    /// get a column with the fraction of the total.
+    pub fn to_delimited(&self, sep: char) -> String {
+        let ratios = self
+            .events
+            .iter()
+            .filter(|x| self.totals.contains_key(*x))
+            .collect::<Vec<_>>();
+
+        let mut header = vec![
+            "function".to_string(),
+            "file".to_string(),
+            "crate".to_string(),
+        ];
+        header.extend(self.events.iter().cloned());
+        header.extend(ratios.iter().map(|x| format!("{}_ratio", x)));
+
+        let mut out = String::new();
+        let row = |fields: Vec<String>| {
+            fields
+                .iter()
+                .map(|x| quote(x, sep))
+                .collect::<Vec<_>>()
+                .join(&sep.to_string())
+                + "\n"
+        };
+        out.push_str(&row(header));
+
+        for function in self.functions.iter() {
+            let metric = |x: &String| function.metrics.get(x).cloned().unwrap_or(0.);
+            let mut fields = vec![
+                function.name.clone(),
+                function.file.clone(),
+                crate_name(&function.name).to_string(),
+            ];
+            fields.extend(self.events.iter().map(|x| metric(x).to_string()));
+            fields.extend(ratios.iter().map(|x| {
+                let total = self.totals[*x];
+                let ratio = if total > 0. { metric(x) / total } else { 0. };
+                ratio.to_string()
+            }));
+            out.push_str(&row(fields));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{crate_name, module_name, split_label, GroupBy, Metadata};
+    use crate::backend::Results;
+    use crate::parse::cachegrind::{self, Latencies};
+    use crate::parse::callgrind;
+
+    #[test]
+    fn test_split_label() {
+        assert_eq!(split_label("main.rs:foo::main"), ("main.rs", "foo::main"));
+        assert_eq!(split_label("memset"), ("???", "memset"));
+    }
+
+    #[test]
+    fn test_report_json() {
+        let output = "events: Ir\nfl=main.rs\nfn=foo::main::h0000000000000001\n1 5\ncfn=foo::work::h0000000000000002\n\
+                      calls=1 2\n1 10\nfn=foo::work::h0000000000000002\n2 10\n";
+        let results = callgrind::parse(output, 10, false, GroupBy::Function).unwrap();
+        let metadata = Metadata::fixture("target/debug/foo", &["--bar"]);
+        let json = serde_json::to_value(results.report(metadata)).unwrap();
+
+        assert_eq!(json["schema_version"], 1);
+        assert_eq!(json["profiler"], "callgrind");
+        assert_eq!(json["metadata"]["args"][0], "--bar");
+        assert_eq!(json["totals"]["Ir"], 15.);
+        assert_eq!(json["functions"][0]["name"], "foo::work");
+        assert_eq!(json["functions"][1]["file"], "main.rs");
+        assert_eq!(json["functions"][1]["metrics"]["Ir_inclusive"], 15.);
+    }
+
+    #[test]
+    fn test_crate_name() {
+        assert_eq!(
+            crate_name("matrixmultiply::gemm::masked_kernel"),
+            "matrixmultiply"
+        );
+        assert_eq!(
+            crate_name("<alloc::vec::Vec<T> as core::ops::Drop>::drop"),
+            "alloc"
+        );
+        assert_eq!(crate_name("<foo::Bar as Clone>::clone"), "foo");
+        assert_eq!(crate_name("<Range<usize> as Iterator>::next"), "");
+        assert_eq!(crate_name("memset"), "");
+    }
+
+    #[test]
+    fn test_module_name() {
+        assert_eq!(module_name("foo::main"), "foo");
+        assert_eq!(
+            module_name("serde_json::de::Deserializer<R>::parse_value"),
+            "serde_json::de"
+        );
+        assert_eq!(
+            module_name("core::ptr::drop_in_place<foo::Bar>"),
+            "core::ptr"
+        );
+        assert_eq!(module_name("foo::bar::{{closure}}"), "foo::bar");
+        assert_eq!(
+            module_name("<foo::cache::Cache as Clone>::clone"),
+            "foo::cache"
+        );
+        assert_eq!(module_name("memset"), "");
+    }
+
+    #[test]
+    fn test_group_by() {
+        let func = "_ZN10serde_json2de12Deserializer11parse_value17h0123456789abcdefE";
+        assert_eq!(
+            GroupBy::Function.label("/src/de.rs", func),
+            "de.rs:serde_json::de::Deserializer::parse_value"
+        );
+        assert_eq!(GroupBy::File.label("/src/de.rs", func), "de.rs");
+        assert_eq!(GroupBy::Crate.label("/src/de.rs", func), "serde_json");
+        assert_eq!(GroupBy::Module.label("/src/de.rs", func), "serde_json::de");
+        assert_eq!(GroupBy::Crate.label("memset.S", "memset"), "???");
+        assert_eq!("module".parse::<GroupBy>().unwrap(), GroupBy::Module);
+        assert!("package".parse::<GroupBy>().is_err());
+
+        assert_eq!(split_label("serde_json::de"), ("???", "serde_json::de"));
+        assert_eq!(
+            split_label("a.rs:<T as foo::Bar>::baz"),
+            ("a.rs", "<T as foo::Bar>::baz")
+        );
+    }
+
+    #[test]
+    fn test_report_csv() {
+        let output = "events: Ir Dr\nfl=/src/main.rs\nfn=foo::main::h0000000000000001\n1 30 4\n\
+                      fn=foo::a,b::h0000000000000002\n2 10\nfl=memset.S\nfn=memset\n1 10 4\n";
+        let results =
+            cachegrind::parse(output, 10, None, &Latencies::default(), GroupBy::Function).unwrap();
+        let metadata = Metadata::fixture("foo", &[]);
+        let report = results.report(metadata);
+
+        let csv = report.to_delimited(',');
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            "function,file,crate,Ir,Dr,Ir_ratio,Dr_ratio"
+        );
+        assert_eq!(lines.next().unwrap(), "foo::main,main.rs,foo,30,4,0.6,0.5");
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("\"foo::a,b\",main.rs,foo,10,"));
+        assert!(lines.next().unwrap().starts_with("memset,memset.S,,10,"));
+
+        let tsv = report.to_delimited('\t');
+        assert!(tsv.contains("foo::a,b\tmain.rs\tfoo\t10\t"));
+    }
+}